@@ -4,30 +4,30 @@ use chrono::Duration;
 /// Makes everything public, adds serde attributes, derives Debug and Clone.
 macro_rules! contract {
     () => {};
-    ($(#[$attr:meta])?
+    ($(#[$attr:meta])*
     struct $name:ident {
-        $($field:ident: $ty:ty),* $(,)?
+        $($(#[$field_attr:meta])* $field:ident: $ty:ty),* $(,)?
     }
     $($rest:tt)*) => {
         #[derive(Debug, Serialize, Deserialize, Clone)]
         #[serde(rename_all = "camelCase")]
-        $(#[$attr])?
+        $(#[$attr])*
         pub struct $name {
-            $(pub $field: $ty),*
+            $($(#[$field_attr])* pub $field: $ty),*
         }
 
         contract! { $($rest)* }
     };
-    ($(#[$attr:meta])?
+    ($(#[$attr:meta])*
     enum $name:ident {
-        $($case:ident),* $(,)?
+        $($(#[$case_attr:meta])* $case:ident),* $(,)?
     }
     $($rest:tt)*) => {
         #[derive(Debug, Serialize, Deserialize, Clone)]
         #[serde(rename_all = "camelCase")]
-        $(#[$attr])?
+        $(#[$attr])*
         pub enum $name {
-            $($case),*
+            $($(#[$case_attr])* $case),*
         }
 
         contract! { $($rest)* }
@@ -73,7 +73,7 @@ contract! {
         NotTaken,
     }
 
-    #[derive(Copy)]
+    #[derive(PartialEq, Eq, Copy)]
     enum Move {
         Up,
         Down,
@@ -86,23 +86,84 @@ contract! {
         user: Option<String>,
         password: Option<String>,
         program: Program,
+        /// Practice submissions are accepted on closed levels, but never
+        /// affect scores.
+        practice: Option<bool>,
+        /// Past level to practice on, as in `Submission::level`. Implies
+        /// `practice`.
+        level: Option<u64>,
     }
 
     struct Submissions {
         submissions: Vec<Submission>,
+        /// Number of matching submissions, before paging.
+        total: u64,
         level_closed: bool,
         level: LevelState,
     }
 
     struct Submission {
         id: u64,
+        /// Index of the level in game history, the current level is the last one.
+        level: u64,
         user: String,
+        status: EvaluationStatus,
+        /// RFC 3339 time.
+        submitted_at: String,
+        outcome: Option<Outcome>,
+        rule_count: u64,
+        step_count: Option<u64>,
+        practice: bool,
+    }
+
+    #[derive(Default)]
+    struct SubmissionsQuery {
+        user: Option<String>,
+        outcome: Option<Outcome>,
+        order: Option<SubmissionOrder>,
+        offset: Option<u64>,
+        limit: Option<u64>,
+    }
+
+    #[derive(PartialEq, Eq, Copy)]
+    enum SubmissionOrder {
+        Oldest,
+        Newest,
+    }
+
+    /// User's submissions across all levels.
+    struct SubmissionHistory {
+        submissions: Vec<Submission>,
+        total: u64,
+    }
+
+    #[derive(PartialEq, Eq, Copy)]
+    enum EvaluationStatus {
+        Queued,
+        Running,
+        Done,
+        /// The level was changed before the submission was evaluated, it
+        /// is queued again if the change is undone.
+        Dropped,
+    }
+
+    struct SubmissionStatus {
+        id: u64,
+        status: EvaluationStatus,
+        outcome: Option<Outcome>,
     }
 
     struct SubmissionDetails {
         initial_state: LevelState,
         steps: Vec<Step>,
+        /// Success only if the public map and all hidden variants were won.
         outcome: Outcome,
+        /// Outcome on each hidden variant of the level, in order.
+        #[serde(default)]
+        hidden_outcomes: Vec<Outcome>,
+        /// Submitted program, given along with the replay to whoever may
+        /// see the submission under the visibility policy.
+        program: Option<Program>,
     }
 
     #[derive(PartialEq, Eq, Copy)]
@@ -127,6 +188,52 @@ contract! {
         objects: Vec<Object>,
     }
 
+    /// Same as `SubmissionDetails`, but each step only lists objects that
+    /// changed since the previous step.
+    struct CompactSubmissionDetails {
+        initial_state: LevelState,
+        steps: Vec<StepDelta>,
+        outcome: Outcome,
+        #[serde(default)]
+        hidden_outcomes: Vec<Outcome>,
+        program: Option<Program>,
+    }
+
+    struct StepDelta {
+        changed: Vec<Object>,
+        removed: Vec<u64>,
+    }
+
+    #[derive(Copy)]
+    enum ReplayFormat {
+        Full,
+        Compact,
+    }
+
+    struct ReplayQuery {
+        format: Option<ReplayFormat>,
+        /// Index of the level in game history, the current one if not given.
+        level: Option<u64>,
+    }
+
+    #[derive(Copy)]
+    enum SvgMode {
+        Animated,
+        Filmstrip,
+    }
+
+    struct SvgQuery {
+        mode: Option<SvgMode>,
+        /// Index of the level in game history, the current one if not given.
+        level: Option<u64>,
+    }
+
+    struct StatusQuery {
+        /// Index of the level in game history, the current one if not given.
+        level: Option<u64>,
+    }
+
+    #[derive(PartialEq, Eq)]
     struct Object {
         id: u64,
         row: u64,
@@ -153,6 +260,28 @@ contract! {
 
     struct Scoreboards {
         scoreboards: Vec<Scoreboard>,
+        /// Results of some submissions are hidden.
+        frozen: bool,
+    }
+
+    struct ScoreboardQuery {
+        /// Admins see the scoreboard without the freeze.
+        admin_token: Option<String>,
+    }
+
+    struct Freeze {
+        admin_token: String,
+    }
+
+    struct Unfreeze {
+        admin_token: String,
+        /// Number of hidden submissions to reveal, defaults to 1.
+        count: Option<u64>,
+    }
+
+    struct UnfreezeResult {
+        /// Submissions that are still hidden.
+        remaining: u64,
     }
 
     struct Scoreboard {
@@ -161,22 +290,131 @@ contract! {
     }
 
     struct ScoreboardEntry {
+        /// Users with equal solved count and penalty share the rank, and
+        /// the next rank is skipped (1, 2, 2, 4).
+        rank: u64,
         user: String,
         solved: u64,
+        /// Penalty by the ranking criterion, the tie breaker after `solved`.
+        penalty: f64,
+        /// In seconds.
+        time_penalty: i64,
+        rule_count: u64,
+        step_count: u64,
+        /// Preformatted penalty, kept for compatibility.
         tie_breaker: String,
+        /// Only given on the current level's scoreboard.
+        best_submissions: Option<BestSubmissions>,
+        /// Submissions until the first success on each level (including
+        /// it), or all failed submissions if the level is not solved.
+        attempts: u64,
+        /// Only given on totals, one per played level with any results in
+        /// order, `null` if the user made no submissions on the level.
+        levels: Vec<Option<LevelScore>>,
     }
 
-    #[derive(Copy)]
+    /// User's result on a single level.
+    struct LevelScore {
+        /// Index of the level in game history, as in `Submission::level`.
+        level: u64,
+        /// Rank on the level, `None` if not solved.
+        rank: Option<u64>,
+        solved: bool,
+        /// In seconds.
+        time_penalty: i64,
+        rule_count: u64,
+        step_count: u64,
+        attempts: u64,
+    }
+
+    /// Ids of submissions that achieved the best value of each metric,
+    /// earliest one if several did.
+    struct BestSubmissions {
+        time: u64,
+        size: u64,
+        steps: u64,
+        /// Best by the ranking criterion.
+        penalty: u64,
+    }
+
+    #[derive(PartialEq, Eq, Copy)]
     enum SubmitResponse {
         Ok,
         RateLimitExceeded,
         LevelClosed,
         Unauthorized,
+        TooManyRules,
+        QueueFull,
+        UnknownLevel,
+    }
+
+    struct SubmitResult {
+        response: SubmitResponse,
+        submission_id: Option<u64>,
+        /// Index of the level in game history the submission was made on,
+        /// to be given as `level` when looking the submission up.
+        level: Option<u64>,
+    }
+
+    struct Sandbox {
+        user: Option<String>,
+        password: Option<String>,
+        level: Level,
+        program: Program,
+    }
+
+    struct SandboxResult {
+        response: SandboxResponse,
+        /// Why the level was rejected.
+        error: Option<String>,
+        details: Option<SubmissionDetails>,
+    }
+
+    #[derive(PartialEq, Eq, Copy)]
+    enum SandboxResponse {
+        Ok,
+        Unauthorized,
+        RateLimitExceeded,
+        InvalidLevel,
     }
 
     struct Level {
+        /// Public map, shown to users and used for replays.
         state: LevelState,
         ghost_program: Program,
+        max_steps: Option<u64>,
+        max_rules: Option<u64>,
+        /// Other maps that are not shown to users (e.g. with different
+        /// starting positions), a submission must win all of them too.
+        #[serde(default)]
+        hidden_variants: Vec<LevelState>,
+        /// How users are ranked on this level, by step count if not set.
+        ranking: Option<Ranking>,
+    }
+
+    struct Ranking {
+        criterion: RankingCriterion,
+        /// Only used by the weighted criterion.
+        weights: Option<RankingWeights>,
+    }
+
+    #[derive(PartialEq, Eq, Copy)]
+    enum RankingCriterion {
+        Time,
+        Size,
+        Steps,
+        Weighted,
+    }
+
+    /// Penalty is time (in seconds), rule count and step count multiplied
+    /// by these weights and summed.
+    struct RankingWeights {
+        #[serde(default)]
+        time: f64,
+        #[serde(default)]
+        size: f64,
+        #[serde(default)]
+        steps: f64,
     }
 
     struct SetLevel {
@@ -184,15 +422,77 @@ contract! {
         level: Level,
     }
 
+    struct Schedule {
+        levels: Vec<ScheduledLevel>,
+    }
+
+    struct ScheduledLevel {
+        level: Level,
+        /// RFC 3339 time.
+        opens_at: String,
+        /// In seconds.
+        duration: u64,
+    }
+
+    struct SetSchedule {
+        admin_token: String,
+        schedule: Schedule,
+    }
+
+    struct ScheduleInfo {
+        levels: Vec<ScheduleEntry>,
+        /// Index of the scheduled level that is currently open.
+        current: Option<u64>,
+        /// Until the current level closes, or the next one opens.
+        seconds_until_change: Option<i64>,
+    }
+
+    struct ScheduleEntry {
+        opens_at: String,
+        closes_at: String,
+    }
+
     struct SetLevelState {
         admin_token: String,
         is_closed: bool,
     }
 
+    struct UndoLevelChange {
+        admin_token: String,
+        steps: Option<u32>,
+    }
+
     struct Reset {
         admin_token: String
     }
 
+    struct Rejudge {
+        admin_token: String,
+        /// Replaces the current level before rejudging.
+        level: Option<Level>,
+    }
+
+    struct RejudgeResult {
+        changed: Vec<RejudgedSubmission>,
+    }
+
+    struct RejudgedSubmission {
+        id: u64,
+        user: String,
+        /// `None` if the submission was not evaluated before.
+        previous_outcome: Option<Outcome>,
+        outcome: Outcome,
+    }
+
+    struct ExportSnapshot {
+        admin_token: String,
+    }
+
+    struct ImportSnapshot {
+        admin_token: String,
+        snapshot: serde_json::Value,
+    }
+
     struct RateLimit {
         admin_token: String,
         user: String,
@@ -200,6 +500,15 @@ contract! {
         window: u32,
     }
 
+    struct PlayMove {
+        next_move: Move,
+    }
+
+    struct PlayStep {
+        step: Step,
+        outcome: Option<Outcome>,
+    }
+
     struct Authenticate {
         user: String,
         password: String,
@@ -20,44 +20,80 @@ pub fn evaluate_program(
     program: &Program,
     move_limit: u64,
 ) -> SubmissionDetails {
+    let mut simulation = Simulation::new(level);
+    let mut driver = program;
     let initial_state = level.state.clone();
     let mut steps = Vec::new();
-    let mut steps_taken = 0;
-    let mut evaluator = Evaluator {
-        cells: &level.state.cells,
-        objects: level.state.objects
-            .iter()
-            .map(|obj| ObjectInfo {
-                obj: obj.clone(),
-                state: RuleState::A,
-                next_row: obj.row as usize,
-                next_col: obj.col as usize,
-            })
-            .collect(),
-        pacman_program: &program,
-        ghost_program: &level.ghost_program,
-    };
 
     let outcome = loop {
-        if steps_taken == move_limit {
+        if simulation.steps_taken() == move_limit {
             break Outcome::OutOfMoves;
         }
-        if evaluator.is_victory() {
-            break Outcome::Success;
-        }
-        if evaluator.is_defeat() {
-            break Outcome::Fail;
+        if let Some(outcome) = simulation.outcome() {
+            break outcome;
         }
-        steps_taken += 1;
-        evaluator.cleanup_objects();
-        evaluator.prepare_moves();
-        steps.push(evaluator.get_step());
-        evaluator.finish_moves();
+        steps.push(simulation.step(&mut driver));
     };
 
-    SubmissionDetails { initial_state, steps, outcome }
+    SubmissionDetails {
+        initial_state,
+        steps,
+        outcome,
+        hidden_outcomes: Vec::new(),
+        program: None,
+    }
 }
 
+/// Evaluates the program on the public map and on every hidden variant of
+/// the level. The replay is of the public map, and the outcome is a success
+/// only if all of them were won.
+pub fn evaluate_all_variants(
+    level: &Level,
+    program: &Program,
+    move_limit: u64,
+) -> SubmissionDetails {
+    let mut details = evaluate_program(level, program, move_limit);
+    for variant in &level.hidden_variants {
+        let variant = Level {
+            state: variant.clone(),
+            ghost_program: level.ghost_program.clone(),
+            max_steps: level.max_steps,
+            max_rules: level.max_rules,
+            hidden_variants: Vec::new(),
+            ranking: None,
+        };
+        let outcome = evaluate_program(&variant, program, move_limit).outcome;
+        details.hidden_outcomes.push(outcome);
+    }
+    if details.outcome == Outcome::Success {
+        if let Some(&failed) = details.hidden_outcomes.iter().find(|&&o| o != Outcome::Success) {
+            details.outcome = failed;
+        }
+    }
+    details
+}
+
+/// Picks moves for pacman objects, ghosts always follow the level's program.
+pub trait Driver {
+    /// Returns the next rule state and move for given pacman. Called once
+    /// per pacman on every step, before any object has moved.
+    fn next_move(&mut self, simulation: &Simulation, pacman: &Object, state: RuleState) -> (RuleState, Move);
+}
+
+impl Driver for &Program {
+    fn next_move(&mut self, simulation: &Simulation, pacman: &Object, state: RuleState) -> (RuleState, Move) {
+        simulation.pick_move(self, state, pacman.row as usize, pacman.col as usize)
+    }
+}
+
+/// Makes every pacman do the same move, rule state is left unchanged.
+impl Driver for Move {
+    fn next_move(&mut self, _simulation: &Simulation, _pacman: &Object, state: RuleState) -> (RuleState, Move) {
+        (state, *self)
+    }
+}
+
+#[derive(Clone)]
 struct ObjectInfo {
     obj: Object,
     state: RuleState,
@@ -75,14 +111,97 @@ impl ObjectInfo {
     }
 }
 
-struct Evaluator<'a> {
-    cells: &'a [Vec<Cell>],
+/// Saved state of a simulation, can be restored into the simulation it was
+/// taken from.
+#[derive(Clone)]
+pub struct Snapshot {
+    objects: Vec<ObjectInfo>,
+    steps_taken: u64,
+}
+
+/// Step by step evaluation of a level.
+#[derive(Clone)]
+pub struct Simulation {
+    cells: Vec<Vec<Cell>>,
+    ghost_program: Program,
     objects: Vec<ObjectInfo>,
-    pacman_program: &'a Program,
-    ghost_program: &'a Program,
+    steps_taken: u64,
 }
 
-impl<'a> Evaluator<'a> {
+impl Simulation {
+    pub fn new(level: &Level) -> Self {
+        Simulation {
+            cells: level.state.cells.clone(),
+            ghost_program: level.ghost_program.clone(),
+            objects: level.state.objects
+                .iter()
+                .map(|obj| ObjectInfo {
+                    obj: obj.clone(),
+                    state: RuleState::A,
+                    next_row: obj.row as usize,
+                    next_col: obj.col as usize,
+                })
+                .collect(),
+            steps_taken: 0,
+        }
+    }
+
+    /// Moves all objects once. Does not check if the game is already over,
+    /// see `outcome`.
+    pub fn step(&mut self, driver: &mut dyn Driver) -> Step {
+        self.steps_taken += 1;
+        self.cleanup_objects();
+        self.prepare_moves(driver);
+        let step = self.get_step();
+        self.finish_moves();
+        step
+    }
+
+    /// `Success` or `Fail` if the game is over, `None` otherwise.
+    pub fn outcome(&self) -> Option<Outcome> {
+        if self.is_victory() {
+            Some(Outcome::Success)
+        } else if self.is_defeat() {
+            Some(Outcome::Fail)
+        } else {
+            None
+        }
+    }
+
+    pub fn steps_taken(&self) -> u64 {
+        self.steps_taken
+    }
+
+    /// Objects as they were shown in the last step (including ones that
+    /// died in it).
+    pub fn objects(&self) -> impl Iterator<Item = &Object> {
+        self.objects.iter().map(|o| &o.obj)
+    }
+
+    pub fn rule_state(&self, id: u64) -> Option<RuleState> {
+        self.objects
+            .iter()
+            .find(|o| o.obj.id == id)
+            .map(|o| o.state)
+    }
+
+    /// What a rule would see in the given cell.
+    pub fn cell(&self, row: usize, col: usize) -> RuleCell {
+        self.get_cell(row, col)
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            objects: self.objects.clone(),
+            steps_taken: self.steps_taken,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.objects = snapshot.objects.clone();
+        self.steps_taken = snapshot.steps_taken;
+    }
+
     fn get_step(&self) -> Step {
         Step {
             objects: self.objects
@@ -96,20 +215,28 @@ impl<'a> Evaluator<'a> {
         self.objects.retain(|o| o.obj.state == DeathState::Alive);
     }
 
-    fn prepare_moves(&mut self) {
-        for i in 0..self.objects.len() {
+    fn prepare_moves(&mut self, driver: &mut dyn Driver) {
+        // decide all moves before changing anything, so that every object
+        // sees the same state
+        let decisions = self.objects
+            .iter()
+            .map(|info| match info.obj.kind {
+                ObjectKind::Pacman => Some(driver.next_move(self, &info.obj, info.state)),
+                ObjectKind::Ghost => Some(self.pick_move(
+                    &self.ghost_program,
+                    info.state,
+                    info.obj.row as usize,
+                    info.obj.col as usize,
+                )),
+                ObjectKind::Berry => None,
+            })
+            .collect::<Vec<_>>();
+        for (i, decision) in decisions.into_iter().enumerate() {
             self.objects[i].obj.current_move = Move::Wait;
-            let program = match self.objects[i].obj.kind {
-                ObjectKind::Pacman => self.pacman_program,
-                ObjectKind::Ghost => self.ghost_program,
-                ObjectKind::Berry => continue,
+            let (next_state, next_move) = match decision {
+                Some(decision) => decision,
+                None => continue,
             };
-            let (next_state, next_move) = self.pick_move(
-                program,
-                self.objects[i].state,
-                self.objects[i].obj.row as usize,
-                self.objects[i].obj.col as usize,
-            );
             self.objects[i].state = next_state;
             self.objects[i].obj.current_move = next_move;
             self.objects[i].obj.intended_move = next_move;
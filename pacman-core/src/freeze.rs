@@ -0,0 +1,90 @@
+use std::collections::HashSet;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use crate::{contract, time_format, PacmanGame};
+use crate::scoreboard::Scoreboard;
+
+/// Hides results of scored submissions made after `at` from the public
+/// scoreboard, except for the first `revealed` of them.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Freeze {
+    #[serde(with = "time_format")]
+    at: DateTime<Utc>,
+    revealed: usize,
+}
+
+impl PacmanGame {
+    /// Freezes the public scoreboard as of `at`, does nothing if it is
+    /// already frozen.
+    pub fn freeze_scoreboard(&mut self, at: DateTime<Utc>) {
+        if self.freeze.is_none() {
+            self.freeze = Some(Freeze { at, revealed: 0 });
+        }
+    }
+
+    /// Reveals results of `count` more hidden submissions, in the order
+    /// they were made. Returns how many are still hidden. The scoreboard is
+    /// unfrozen once everything is revealed on a closed level.
+    pub fn unfreeze(&mut self, count: u64) -> u64 {
+        let hidden = self.frozen_submissions().len();
+        let freeze = match &mut self.freeze {
+            Some(freeze) => freeze,
+            None => return 0,
+        };
+        freeze.revealed = std::cmp::min(hidden, freeze.revealed.saturating_add(count as usize));
+        let remaining = hidden - freeze.revealed;
+        if remaining == 0 && self.is_level_closed {
+            self.freeze = None;
+        }
+        remaining as u64
+    }
+
+    /// Scoreboards as seen by `viewer`: while frozen, only revealed results
+    /// are included, apart from viewer's own.
+    pub fn get_scores_for(&self, viewer: Option<&str>) -> contract::Scoreboards {
+        if self.freeze.is_none() {
+            return self.get_scores();
+        }
+        let hidden = self.hidden_submissions(viewer);
+        let users = self.submissions
+            .iter()
+            .filter(|s| !s.practice)
+            .map(|s| s.user.as_str())
+            .collect::<HashSet<_>>();
+        let mut scores = Scoreboard::new();
+        for user in users {
+            self.add_user_scores(&mut scores, user, &|id| !hidden.contains(&id));
+        }
+        self.scoreboards(&scores, &|id| !hidden.contains(&id), true)
+    }
+
+    /// Ids of submissions on the current level whose results are hidden
+    /// from `viewer` by the freeze, i.e. unrevealed ones of other users.
+    /// They are hidden from submission lists, details and status too.
+    pub(crate) fn hidden_submissions(&self, viewer: Option<&str>) -> HashSet<usize> {
+        let freeze = match &self.freeze {
+            Some(freeze) => freeze,
+            None => return HashSet::new(),
+        };
+        self.frozen_submissions()
+            .into_iter()
+            .skip(freeze.revealed)
+            .filter(|&id| Some(self.submissions[id].user.as_str()) != viewer)
+            .collect()
+    }
+
+    /// Ids of scored submissions made since the freeze, revealed or not.
+    fn frozen_submissions(&self) -> Vec<usize> {
+        let at = match &self.freeze {
+            Some(freeze) => freeze.at,
+            None => return Vec::new(),
+        };
+        self.submissions
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| !s.practice && s.submitted_at >= at)
+            .map(|(id, _)| id)
+            .collect()
+    }
+}
@@ -0,0 +1,93 @@
+use std::cmp;
+use std::iter;
+use crate::{contract, PacmanGame, UserSubmission};
+
+/// Page size when the query does not specify one.
+const DEFAULT_LIMIT: u64 = 50;
+const MAX_LIMIT: u64 = 500;
+
+impl UserSubmission {
+    pub(crate) fn to_contract(&self, level: u64, id: u64) -> contract::Submission {
+        contract::Submission {
+            id,
+            level,
+            user: self.user.clone(),
+            status: self.status,
+            submitted_at: self.submitted_at.to_rfc3339(),
+            outcome: self.details.as_ref().map(|d| d.outcome),
+            rule_count: self.program.rules.len() as u64,
+            step_count: self.details.as_ref().map(|d| d.steps.len().saturating_sub(1) as u64),
+            practice: self.practice,
+        }
+    }
+}
+
+impl PacmanGame {
+    /// Submissions on the current level that match the query and that
+    /// `viewer` is allowed to see. Results hidden by a scoreboard freeze are
+    /// left out.
+    pub fn query_submissions(&self, query: &contract::SubmissionsQuery, viewer: Option<&str>) -> contract::Submissions {
+        let level = self.level_index();
+        let hidden = self.hidden_submissions(viewer);
+        let all = self.submissions
+            .iter()
+            .enumerate()
+            .filter(|(_, sub)| self.can_view(sub, false, viewer))
+            .map(|(id, sub)| {
+                let mut submission = sub.to_contract(level, id as u64);
+                if hidden.contains(&id) {
+                    submission.outcome = None;
+                    submission.step_count = None;
+                }
+                submission
+            });
+        let (submissions, total) = select(all, query);
+        contract::Submissions {
+            submissions,
+            total,
+            level_closed: self.is_level_closed,
+            level: self.current_level.state.clone(),
+        }
+    }
+
+    /// User's submissions on all levels, including the ones that were
+    /// already replaced. The user filter of the query is ignored.
+    pub fn user_submissions(&self, user: &str, query: &contract::SubmissionsQuery) -> contract::SubmissionHistory {
+        let all = self.previous_levels
+            .iter()
+            .map(|previous| &previous.submissions)
+            .chain(iter::once(&self.submissions))
+            .enumerate()
+            .flat_map(|(level, submissions)| submissions
+                .iter()
+                .enumerate()
+                .filter(|(_, sub)| sub.user == user)
+                .map(move |(id, sub)| sub.to_contract(level as u64, id as u64)));
+        let query = contract::SubmissionsQuery {
+            user: None,
+            ..query.clone()
+        };
+        let (submissions, total) = select(all, &query);
+        contract::SubmissionHistory { submissions, total }
+    }
+}
+
+/// Filters, orders and pages submissions, which are given oldest first.
+/// Also returns the number of matching submissions before paging.
+fn select(
+    submissions: impl Iterator<Item = contract::Submission>,
+    query: &contract::SubmissionsQuery,
+) -> (Vec<contract::Submission>, u64) {
+    let mut matching = submissions
+        .filter(|s| query.user.is_none() || query.user.as_ref() == Some(&s.user))
+        .filter(|s| query.outcome.is_none() || query.outcome == s.outcome)
+        .collect::<Vec<_>>();
+    let total = matching.len() as u64;
+    if query.order == Some(contract::SubmissionOrder::Newest) {
+        matching.reverse();
+    }
+    let offset = query.offset.unwrap_or(0) as usize;
+    let limit = cmp::min(query.limit.unwrap_or(DEFAULT_LIMIT), MAX_LIMIT) as usize;
+    let page = matching.into_iter().skip(offset).take(limit).collect();
+    (page, total)
+}
@@ -0,0 +1,213 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use crate::{contract, time_format, FinishedEvaluation, PacmanGame, RateLimit};
+use crate::schedule::Schedule;
+
+/// A state changing operation on the game. Applying all recorded events to
+/// a fresh game in order rebuilds the same game.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "event")]
+pub enum Event {
+    #[serde(rename_all = "camelCase")]
+    SetLevel {
+        level: contract::Level,
+        #[serde(with = "time_format")]
+        time: DateTime<Utc>,
+    },
+    #[serde(rename_all = "camelCase")]
+    SetLevelState {
+        closed: bool,
+    },
+    #[serde(rename_all = "camelCase")]
+    Submit {
+        user: String,
+        program: contract::Program,
+        #[serde(with = "time_format")]
+        time: DateTime<Utc>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Practice {
+        user: String,
+        program: contract::Program,
+        level: Option<u64>,
+        #[serde(with = "time_format")]
+        time: DateTime<Utc>,
+    },
+    #[serde(rename_all = "camelCase")]
+    RateLimit {
+        user: String,
+        count: usize,
+        window_seconds: i64,
+    },
+    Reset,
+    #[serde(rename_all = "camelCase")]
+    SetSchedule {
+        schedule: contract::Schedule,
+        #[serde(with = "time_format")]
+        time: DateTime<Utc>,
+    },
+    #[serde(rename_all = "camelCase")]
+    AdvanceSchedule {
+        #[serde(with = "time_format")]
+        time: DateTime<Utc>,
+    },
+    #[serde(rename_all = "camelCase")]
+    UndoLevelChange {
+        steps: usize,
+    },
+    #[serde(rename_all = "camelCase")]
+    ImportSnapshot {
+        snapshot: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    Rejudge {
+        level: Option<contract::Level>,
+    },
+    /// A queued submission was evaluated and its result applied.
+    #[serde(rename_all = "camelCase")]
+    Evaluated {
+        past_level: Option<usize>,
+        id: u64,
+    },
+    #[serde(rename_all = "camelCase")]
+    Freeze {
+        #[serde(with = "time_format")]
+        time: DateTime<Utc>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Unfreeze {
+        count: u64,
+    },
+}
+
+impl Event {
+    /// Encodes the event as a single journal line, without the newline.
+    pub fn to_line(&self) -> String {
+        serde_json::to_string(self).expect("journal events are always serializable")
+    }
+}
+
+impl FinishedEvaluation {
+    /// Event to record once the evaluation was applied.
+    pub fn event(&self) -> Event {
+        Event::Evaluated {
+            past_level: self.past_level,
+            id: self.id,
+        }
+    }
+}
+
+impl PacmanGame {
+    /// Applies a recorded event. Submissions are only queued, and evaluated
+    /// on their `Evaluated` event, because live evaluations that were still
+    /// queued when the level changed are never applied.
+    pub fn apply(&mut self, event: &Event) {
+        match event {
+            Event::SetLevel { level, time } => {
+                self.set_level(level.clone(), *time);
+            }
+            Event::SetLevelState { closed } => {
+                self.set_level_state(*closed);
+            }
+            Event::Submit { user, program, time } => {
+                if let Err(response) = self.queue_program(user, program, *time) {
+                    log::warn!("replayed submission by {} was not accepted: {:?}", user, response);
+                }
+            }
+            Event::Practice { user, program, level, time } => {
+                if let Err(response) = self.queue_practice(user, program, *level, *time) {
+                    log::warn!("replayed practice submission by {} was not accepted: {:?}", user, response);
+                }
+            }
+            Event::RateLimit { user, count, window_seconds } => {
+                self.rate_limit_user(user, RateLimit {
+                    count: *count,
+                    window: Duration::seconds(*window_seconds),
+                });
+            }
+            Event::Reset => {
+                self.reset();
+            }
+            Event::SetSchedule { schedule, time } => {
+                match Schedule::from_contract(schedule, *time) {
+                    Ok(schedule) => self.set_schedule(schedule, *time),
+                    Err(e) => log::error!("failed to replay schedule: {}", e),
+                }
+            }
+            Event::AdvanceSchedule { time } => {
+                self.advance_schedule(*time);
+            }
+            Event::UndoLevelChange { steps } => {
+                self.undo_level_change(*steps);
+            }
+            Event::ImportSnapshot { snapshot } => {
+                if let Err(e) = self.import_snapshot(snapshot) {
+                    log::error!("failed to replay snapshot import: {}", e);
+                }
+            }
+            Event::Rejudge { level } => {
+                self.start_rejudge(level.clone());
+            }
+            Event::Evaluated { past_level, id } => {
+                match self.pending_submission(self.generation, *past_level, *id) {
+                    Some(_) => {
+                        let pending = self.pending_evaluation(*past_level, *id);
+                        self.evaluate_now(pending);
+                    }
+                    None => log::warn!("replayed evaluation of submission {} is not pending", id),
+                }
+            }
+            Event::Freeze { time } => {
+                self.freeze_scoreboard(*time);
+            }
+            Event::Unfreeze { count } => {
+                self.unfreeze(*count);
+            }
+        }
+    }
+}
+
+/// Parses a journal, one json encoded event per line. An unfinished last
+/// line (left by a crash in the middle of writing it) is skipped.
+pub fn read_events(text: &str) -> Result<Vec<Event>, serde_json::Error> {
+    let lines = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect::<Vec<_>>();
+    let mut events = Vec::with_capacity(lines.len());
+    for (index, line) in lines.iter().enumerate() {
+        match serde_json::from_str(line) {
+            Ok(event) => events.push(event),
+            Err(e) if index + 1 == lines.len() && !text.ends_with('\n') => {
+                log::warn!("skipping unfinished journal entry: {}", e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_roundtrip() {
+        let event = Event::RateLimit {
+            user: "user".to_owned(),
+            count: 3,
+            window_seconds: 60,
+        };
+        let json = event.to_line();
+        assert_eq!(json, r#"{"event":"rateLimit","user":"user","count":3,"windowSeconds":60}"#);
+        let events = read_events(&format!("{}\n\n{}\n", json, r#"{"event":"reset"}"#)).unwrap();
+        assert_eq!(events.len(), 2);
+    }
+
+    #[test]
+    fn skips_unfinished_entry() {
+        let events = read_events("{\"event\":\"reset\"}\n{\"event\":\"setLev").unwrap();
+        assert_eq!(events.len(), 1);
+        assert!(read_events("{\"event\":\"setLev\n{\"event\":\"reset\"}\n").is_err());
+    }
+}
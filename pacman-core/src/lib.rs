@@ -1,15 +1,27 @@
 #![allow(unused)]
 
 pub mod contract;
+pub mod freeze;
+pub mod journal;
+pub mod replay;
+pub mod sandbox;
+pub mod schedule;
+pub mod svg;
+mod history;
 mod rate_limiter;
 mod scoreboard;
 mod evaluator;
+mod snapshot;
+mod time_format;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
 use rate_limiter::{RateLimiter, RateLimitExceeded};
 use scoreboard::Scoreboard;
 
+pub use evaluator::{Driver, Simulation, Snapshot};
+
 #[derive(Debug, Copy, Clone)]
 pub struct RateLimit {
     pub count: usize,
@@ -18,17 +30,135 @@ pub struct RateLimit {
 
 #[derive(Debug, Clone)]
 pub struct GameConfig {
+    /// Step limit for levels that don't specify their own.
     pub max_steps: u64,
     pub rate_limit: RateLimit,
+    /// Max number of submissions waiting for evaluation at once.
+    pub max_queued: usize,
+    pub visibility: Visibility,
+    /// Step cap for sandbox evaluations, applied even if the level allows
+    /// more.
+    pub sandbox_max_steps: u64,
+    pub sandbox_rate_limit: RateLimit,
+    /// Time penalty for every failed submission before user's first
+    /// success on a level, no penalty if not set.
+    pub wrong_attempt_penalty: Option<Duration>,
+    /// Scheduled levels freeze the public scoreboard this long before they
+    /// close.
+    pub scoreboard_freeze: Option<Duration>,
+    /// Keep the scoreboard frozen after the level closes until results are
+    /// revealed with `unfreeze`.
+    pub manual_unfreeze: bool,
+    /// Team of each user that is in one, team scoreboards are only shown if
+    /// there are any.
+    pub teams: HashMap<String, String>,
+    /// Rate limit members of a team together instead of each user.
+    pub team_rate_limit: bool,
+}
+
+/// Who can see replays and programs of other users' submissions.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Visibility {
+    /// Users only see their own submissions.
+    Own,
+    /// Everyone's submissions can be seen once the level is closed.
+    AfterClose,
+    /// Everyone's submissions can be seen at any time.
+    Public,
 }
 
+#[derive(Serialize, Deserialize, Clone)]
 struct UserSubmission {
     user: String,
+    #[serde(with = "time_format")]
+    submitted_at: DateTime<Utc>,
+    program: contract::Program,
+    status: contract::EvaluationStatus,
+    details: Option<contract::SubmissionDetails>,
+    /// Practice submissions never affect scores.
+    #[serde(default)]
+    practice: bool,
+    /// Queued again by a rejudge, which doesn't count towards the queue
+    /// limit.
+    #[serde(default)]
+    rejudged: bool,
+}
+
+impl UserSubmission {
+    /// Waiting for an evaluation that will still be applied.
+    fn is_pending(&self) -> bool {
+        self.status == contract::EvaluationStatus::Queued
+            || self.status == contract::EvaluationStatus::Running
+    }
+}
+
+/// A submission that was accepted but not yet evaluated.
+pub struct PendingEvaluation {
+    pub id: u64,
+    /// Index of the level in game history, as in `contract::Submission::level`.
+    pub level_index: u64,
+    generation: u64,
+    /// Index into previous levels, `None` for the current level.
+    past_level: Option<usize>,
+    level: contract::Level,
+    program: contract::Program,
+    max_steps: u64,
+}
+
+impl PendingEvaluation {
+    pub fn evaluate(self) -> FinishedEvaluation {
+        let details = evaluator::evaluate_all_variants(
+            &self.level,
+            &self.program,
+            self.max_steps,
+        );
+        FinishedEvaluation {
+            id: self.id,
+            generation: self.generation,
+            past_level: self.past_level,
+            details,
+        }
+    }
+}
+
+pub struct FinishedEvaluation {
+    id: u64,
+    generation: u64,
+    past_level: Option<usize>,
     details: contract::SubmissionDetails,
 }
 
+/// A rejudge in progress, see `PacmanGame::start_rejudge`.
+pub struct Rejudge {
+    generation: u64,
+    /// By submission id.
+    previous_outcomes: Vec<Option<contract::Outcome>>,
+}
+
+/// State replaced by a level change, kept so that the change can be undone.
+#[derive(Serialize, Deserialize, Clone)]
+struct PreviousLevel {
+    global_scores: Scoreboard,
+    #[serde(default)]
+    team_scores: Scoreboard,
+    level_scores: Scoreboard,
+    level: contract::Level,
+    limiters: HashMap<String, RateLimiter>,
+    is_closed: bool,
+    #[serde(with = "time_format")]
+    start: DateTime<Utc>,
+    submissions: Vec<UserSubmission>,
+    #[serde(default)]
+    freeze: Option<freeze::Freeze>,
+    /// Schedule progress before the level was replaced.
+    #[serde(default)]
+    schedule: Option<schedule::Progress>,
+}
+
 pub struct PacmanGame {
     global_scores: Scoreboard,
+    /// Team totals from previous levels.
+    team_scores: Scoreboard,
     level_scores: Scoreboard,
     current_level: contract::Level,
     limiters: HashMap<String, RateLimiter>,
@@ -36,12 +166,27 @@ pub struct PacmanGame {
     config: GameConfig,
     level_start: DateTime<Utc>,
     submissions: Vec<UserSubmission>,
+    /// Oldest first.
+    previous_levels: Vec<PreviousLevel>,
+    schedule: schedule::Schedule,
+    /// Manual play on the current level, by user.
+    play_sessions: HashMap<String, Simulation>,
+    /// Set while the public scoreboard is frozen.
+    freeze: Option<freeze::Freeze>,
+    sandbox_limiters: HashMap<String, RateLimiter>,
+    /// Bumped on every level change and reset so that evaluations queued
+    /// before it are not applied to the new level.
+    generation: u64,
+    /// Index of the first level in game history, past the levels of the
+    /// score dump the game was continued from.
+    level_offset: u64,
 }
 
 impl PacmanGame {
     pub fn new(config: GameConfig) -> Self {
         PacmanGame {
             global_scores: Scoreboard::new(),
+            team_scores: Scoreboard::new(),
             level_scores: Scoreboard::new(),
             current_level: empty_level(),
             limiters: HashMap::new(),
@@ -49,11 +194,18 @@ impl PacmanGame {
             config,
             level_start: Utc.timestamp(0, 0),
             submissions: Vec::new(),
+            previous_levels: Vec::new(),
+            schedule: schedule::Schedule::default(),
+            play_sessions: HashMap::new(),
+            freeze: None,
+            sandbox_limiters: HashMap::new(),
+            generation: 0,
+            level_offset: 0,
         }
     }
 
     pub fn from_raw_scoreboard(config: GameConfig, raw: &str) -> Result<Self, ()> {
-        let global_scores = match serde_json::from_str(raw) {
+        let global_scores: Scoreboard = match serde_json::from_str(raw) {
             Ok(s) => s,
             Err(e) => {
                 log::error!("failed to deserialize raw scores: {}", e);
@@ -61,6 +213,7 @@ impl PacmanGame {
             }
         };
         let mut game = Self::new(config);
+        game.level_offset = global_scores.next_level();
         game.global_scores = global_scores;
         Ok(game)
     }
@@ -80,92 +233,619 @@ impl PacmanGame {
     }
 
     pub fn set_level(&mut self, level: contract::Level, now: DateTime<Utc>) {
-        self.global_scores.add_level_scores(&self.level_scores);
-        self.level_scores = Scoreboard::new();
-        self.current_level = level;
-        self.limiters.clear();
+        self.drop_unfinished();
+        let previous = PreviousLevel {
+            global_scores: self.global_scores.clone(),
+            team_scores: self.team_scores.clone(),
+            level_scores: std::mem::take(&mut self.level_scores),
+            level: std::mem::replace(&mut self.current_level, level),
+            limiters: std::mem::take(&mut self.limiters),
+            is_closed: self.is_level_closed,
+            start: self.level_start,
+            submissions: std::mem::take(&mut self.submissions),
+            freeze: self.freeze.take(),
+            schedule: Some(self.schedule.progress()),
+        };
+        if self.config.wrong_attempt_penalty.is_some() && !ranks_by_time(level_ranking(&self.current_level, &self.config).as_ref()) {
+            log::warn!("new level is not ranked by time, wrong attempt penalties won't affect standings");
+        }
+        let index = self.level_index();
+        self.global_scores.add_level_scores(index, &previous.level_scores);
+        let team_level_scores = self.team_level_scores(&previous.submissions, &previous.level, previous.start, &|_| true);
+        self.team_scores.add_level_scores(index, &team_level_scores);
+        self.previous_levels.push(previous);
         self.is_level_closed = false;
         self.level_start = now;
-        self.submissions.clear();
+        self.play_sessions.clear();
+        self.generation += 1;
+    }
+
+    /// Reverts the last `steps` level changes, restoring the level that was
+    /// replaced along with its submissions, scores and the progress of the
+    /// schedule (so undone scheduled levels are opened again when due, if
+    /// the schedule wasn't changed since). Returns `None` without
+    /// changing anything if there were fewer level changes than that.
+    /// Submissions that were not evaluated before the level was replaced are
+    /// returned to be queued again.
+    pub fn undo_level_change(&mut self, steps: usize) -> Option<Vec<PendingEvaluation>> {
+        if steps == 0 || steps > self.previous_levels.len() {
+            return None;
+        }
+        let keep = self.previous_levels.len() - steps;
+        let previous = self.previous_levels.drain(keep..).next()?;
+        self.global_scores = previous.global_scores;
+        self.team_scores = previous.team_scores;
+        self.level_scores = previous.level_scores;
+        self.current_level = previous.level;
+        self.limiters = previous.limiters;
+        self.is_level_closed = previous.is_closed;
+        self.level_start = previous.start;
+        self.submissions = previous.submissions;
+        self.freeze = previous.freeze;
+        if let Some(progress) = previous.schedule {
+            self.schedule.restore(progress);
+        }
+        self.play_sessions.clear();
+        self.generation += 1;
+        Some(self.requeue_unfinished())
+    }
+
+    /// Evaluations queued before the generation is bumped are never
+    /// applied, so submissions waiting for them are marked as dropped.
+    fn drop_unfinished(&mut self) {
+        let submissions = self.previous_levels
+            .iter_mut()
+            .flat_map(|previous| &mut previous.submissions)
+            .chain(&mut self.submissions);
+        for submission in submissions {
+            if submission.is_pending() {
+                submission.status = contract::EvaluationStatus::Dropped;
+            }
+            submission.rejudged = false;
+        }
+    }
+
+    /// Queues everything on the current level that was not evaluated yet
+    /// again, dropping unfinished submissions on past levels.
+    fn requeue_unfinished(&mut self) -> Vec<PendingEvaluation> {
+        self.drop_unfinished();
+        let mut pending = Vec::new();
+        for id in 0..self.submissions.len() {
+            if self.submissions[id].status != contract::EvaluationStatus::Done {
+                self.submissions[id].status = contract::EvaluationStatus::Queued;
+                pending.push(self.pending_evaluation(None, id as u64));
+            }
+        }
+        pending
+    }
+
+    /// Resets the whole game to a fresh state.
+    pub fn reset(&mut self) {
+        let generation = self.generation + 1;
+        *self = PacmanGame::new(self.config.clone());
+        self.generation = generation;
     }
 
     pub fn set_level_state(&mut self, closed: bool) {
         self.is_level_closed = closed;
+        if closed && !self.config.manual_unfreeze {
+            self.freeze = None;
+        }
     }
 
+    /// Full scoreboards, ignoring the freeze.
     pub fn get_scores(&self) -> contract::Scoreboards {
+        self.scoreboards(&self.level_scores, &|_| true, false)
+    }
+
+    /// Team scoreboards only count submissions on the current level for
+    /// which `include` returns true (given submission id).
+    fn scoreboards(&self, level_scores: &Scoreboard, include: &dyn Fn(usize) -> bool, frozen: bool) -> contract::Scoreboards {
+        let index = self.level_index();
         let mut global = self.global_scores.clone();
-        global.add_level_scores(&self.level_scores);
+        global.add_level_scores(index, level_scores);
+        let criterion = level_ranking(&self.current_level, &self.config)
+            .map_or(contract::RankingCriterion::Steps, |r| r.criterion);
+        let mut scoreboards = vec![
+            level_scores.to_contract("Results", Some(criterion)),
+            global.to_contract("Total", None),
+        ];
+        if !self.config.teams.is_empty() {
+            let team_level_scores = self.team_level_scores(&self.submissions, &self.current_level, self.level_start, include);
+            let mut team_global = self.team_scores.clone();
+            team_global.add_level_scores(index, &team_level_scores);
+            scoreboards.push(team_level_scores.to_contract("Team results", Some(criterion)));
+            scoreboards.push(team_global.to_contract("Team total", None));
+        }
         contract::Scoreboards {
-            scoreboards: vec![
-                self.level_scores.to_contract_with_speed("Results"),
-                global.to_contract_with_speed("Total"),
-            ],
+            scoreboards,
+            frozen,
         }
     }
 
+    /// Best results of each team on a level, as if all submissions of its
+    /// members were made by the team.
+    fn team_level_scores(&self, submissions: &[UserSubmission], level: &contract::Level, start: DateTime<Utc>, include: &dyn Fn(usize) -> bool) -> Scoreboard {
+        let mut scores = Scoreboard::new();
+        let teams = submissions
+            .iter()
+            .filter_map(|s| self.config.teams.get(&s.user))
+            .collect::<HashSet<_>>();
+        for team in teams {
+            let in_team = |id: usize, s: &UserSubmission| {
+                self.config.teams.get(&s.user) == Some(team) && include(id)
+            };
+            add_scores(&mut scores, team, submissions, level, start, &self.config, &in_team);
+        }
+        scores
+    }
+
+    /// Limiters are shared by team members if teams are rate limited
+    /// together.
+    fn limiter_key(&self, user: &str) -> String {
+        match self.config.teams.get(user) {
+            Some(team) if self.config.team_rate_limit => format!("team:{}", team),
+            _ => user.to_owned(),
+        }
+    }
+
+    /// Overrides the rate limit of the user, or of the user's whole team if
+    /// teams are rate limited together.
     pub fn rate_limit_user(&mut self, user: &str, limit: RateLimit) {
-        if let Some(limiter) = self.limiters.get_mut(user) {
+        let key = self.limiter_key(user);
+        if let Some(limiter) = self.limiters.get_mut(&key) {
             limiter.configure(limit.count, limit.window);
         } else {
-            self.limiters.insert(user.to_owned(), RateLimiter::new(limit.count, limit.window));
+            self.limiters.insert(key, RateLimiter::new(limit.count, limit.window));
         }
     }
 
+    /// Evaluates the program right away, used when there is no worker pool.
     pub fn submit_program(&mut self, user: &str, program: &contract::Program, now: DateTime<Utc>) -> contract::SubmitResponse {
+        match self.queue_program(user, program, now) {
+            Ok(pending) => {
+                self.evaluate_now(pending);
+                contract::SubmitResponse::Ok
+            }
+            Err(response) => response,
+        }
+    }
+
+    pub fn evaluate_now(&mut self, pending: PendingEvaluation) {
+        if self.start_evaluation(&pending) {
+            self.finish_evaluation(pending.evaluate());
+        }
+    }
+
+    /// Accepts a submission without evaluating it. The returned evaluation
+    /// should be run outside of the game lock and passed back to
+    /// `finish_evaluation`.
+    pub fn queue_program(&mut self, user: &str, program: &contract::Program, now: DateTime<Utc>) -> Result<PendingEvaluation, contract::SubmitResponse> {
         if self.is_level_closed {
-            return contract::SubmitResponse::LevelClosed;
+            return Err(contract::SubmitResponse::LevelClosed);
+        }
+        self.queue(user, program, None, false, now)
+    }
+
+    /// Same as `queue_program`, but for practice submissions, which never
+    /// affect scores. They are accepted on closed levels too, and on past
+    /// levels given by their index in game history (as in
+    /// `contract::Submission::level`). `None` practices on the current level.
+    pub fn queue_practice(&mut self, user: &str, program: &contract::Program, level: Option<u64>, now: DateTime<Utc>) -> Result<PendingEvaluation, contract::SubmitResponse> {
+        let past_level = self.past_level(level).ok_or(contract::SubmitResponse::UnknownLevel)?;
+        self.queue(user, program, past_level, true, now)
+    }
+
+    /// Index into previous levels of the level with the given index in game
+    /// history, `Some(None)` for the current level (also if not given) and
+    /// `None` for unknown levels.
+    fn past_level(&self, level: Option<u64>) -> Option<Option<usize>> {
+        let current = self.level_index();
+        match level {
+            Some(level) if level < self.level_offset || level > current => None,
+            Some(level) if level < current => Some(Some((level - self.level_offset) as usize)),
+            _ => Some(None),
+        }
+    }
+
+    /// Index of the current level in game history.
+    fn level_index(&self) -> u64 {
+        self.level_offset + self.previous_levels.len() as u64
+    }
+
+    fn queue(&mut self, user: &str, program: &contract::Program, past_level: Option<usize>, practice: bool, now: DateTime<Utc>) -> Result<PendingEvaluation, contract::SubmitResponse> {
+        if let Some(max_rules) = self.level(past_level).max_rules {
+            if program.rules.len() as u64 > max_rules {
+                return Err(contract::SubmitResponse::TooManyRules);
+            }
+        }
+        let unfinished = self.previous_levels
+            .iter()
+            .flat_map(|previous| &previous.submissions)
+            .chain(&self.submissions)
+            .filter(|s| s.is_pending() && !s.rejudged)
+            .count();
+        if unfinished >= self.config.max_queued {
+            return Err(contract::SubmitResponse::QueueFull);
         }
         let rate_limit = self.config.rate_limit;
+        let key = self.limiter_key(user);
         let can_submit = self.limiters
-            .entry(user.to_owned())
+            .entry(key)
             .or_insert_with(|| RateLimiter::new(rate_limit.count, rate_limit.window))
             .submit(now);
         match can_submit {
             Ok(()) => {
-                let details = evaluator::evaluate_program(
-                    &self.current_level,
-                    &program,
-                    self.config.max_steps,
-                );
-                if details.outcome == contract::Outcome::Success {
-                    let mut time_penalty = (now - self.level_start).num_seconds();
-                    self.level_scores.add_user_evaluation(
-                        user,
-                        time_penalty,
-                        program.rules.len(),
-                        details.steps.len().saturating_sub(1),
-                    );
-                }
-                self.submissions.push(UserSubmission {
+                let submissions = self.level_submissions_mut(past_level);
+                let id = submissions.len() as u64;
+                submissions.push(UserSubmission {
                     user: user.to_owned(),
-                    details,
+                    submitted_at: now,
+                    program: program.clone(),
+                    status: contract::EvaluationStatus::Queued,
+                    details: None,
+                    practice,
+                    rejudged: false,
                 });
-                contract::SubmitResponse::Ok
+                Ok(self.pending_evaluation(past_level, id))
             }
             Err(RateLimitExceeded) => {
-                contract::SubmitResponse::RateLimitExceeded
+                Err(contract::SubmitResponse::RateLimitExceeded)
             }
         }
     }
 
+    fn pending_evaluation(&self, past_level: Option<usize>, id: u64) -> PendingEvaluation {
+        let level = self.level(past_level);
+        PendingEvaluation {
+            id,
+            level_index: past_level.map_or(self.level_index(), |level| self.level_offset + level as u64),
+            generation: self.generation,
+            past_level,
+            level: level.clone(),
+            program: self.level_submissions(past_level)[id as usize].program.clone(),
+            max_steps: level.max_steps.unwrap_or(self.config.max_steps),
+        }
+    }
+
+    fn level(&self, past_level: Option<usize>) -> &contract::Level {
+        match past_level {
+            Some(index) => &self.previous_levels[index].level,
+            None => &self.current_level,
+        }
+    }
+
+    fn level_submissions(&self, past_level: Option<usize>) -> &[UserSubmission] {
+        match past_level {
+            Some(index) => &self.previous_levels[index].submissions,
+            None => &self.submissions,
+        }
+    }
+
+    fn level_submissions_mut(&mut self, past_level: Option<usize>) -> &mut Vec<UserSubmission> {
+        match past_level {
+            Some(index) => &mut self.previous_levels[index].submissions,
+            None => &mut self.submissions,
+        }
+    }
+
+    /// Marks the submission as running. Returns `false` if the submission
+    /// no longer exists (level was changed since it was queued), in which
+    /// case it does not need to be evaluated.
+    pub fn start_evaluation(&mut self, pending: &PendingEvaluation) -> bool {
+        match self.pending_submission(pending.generation, pending.past_level, pending.id) {
+            Some(index) => {
+                self.level_submissions_mut(pending.past_level)[index].status = contract::EvaluationStatus::Running;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Applies the evaluation. Returns `false` if it was dropped because
+    /// the submission no longer waits for it.
+    pub fn finish_evaluation(&mut self, evaluation: FinishedEvaluation) -> bool {
+        let index = match self.pending_submission(evaluation.generation, evaluation.past_level, evaluation.id) {
+            Some(index) => index,
+            None => {
+                log::debug!("dropping stale evaluation of submission {}", evaluation.id);
+                return false;
+            }
+        };
+        let submission = &mut self.level_submissions_mut(evaluation.past_level)[index];
+        submission.status = contract::EvaluationStatus::Done;
+        submission.details = Some(evaluation.details);
+        submission.rejudged = false;
+        if evaluation.past_level.is_none() {
+            let user = self.submissions[index].user.clone();
+            self.rescore_user(&user);
+        }
+        true
+    }
+
+    /// Evaluations of all submissions that are waiting for one, e.g. after
+    /// replaying a journal that ended before they were evaluated.
+    pub fn pending_evaluations(&self) -> Vec<PendingEvaluation> {
+        let past = self.previous_levels
+            .iter()
+            .enumerate()
+            .flat_map(|(level, previous)| previous.submissions
+                .iter()
+                .enumerate()
+                .filter(|(_, s)| s.is_pending())
+                .map(move |(id, _)| (Some(level), id as u64)));
+        let current = self.submissions
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.is_pending())
+            .map(|(id, _)| (None, id as u64));
+        past.chain(current)
+            .map(|(past_level, id)| self.pending_evaluation(past_level, id))
+            .collect()
+    }
+
+    /// Rebuilds user's entry in the level scoreboard from their evaluated
+    /// submissions on the current level, in the order they were submitted.
+    /// Done from scratch because evaluations can finish out of order, and
+    /// wrong attempts only count before the first success.
+    fn rescore_user(&mut self, user: &str) {
+        let mut scores = std::mem::take(&mut self.level_scores);
+        scores.remove_user(user);
+        self.add_user_scores(&mut scores, user, &|_| true);
+        self.level_scores = scores;
+    }
+
+    /// Adds user's evaluated submissions on the current level for which
+    /// `include` returns true (given submission id) to the scoreboard.
+    fn add_user_scores(&self, scores: &mut Scoreboard, user: &str, include: &dyn Fn(usize) -> bool) {
+        let by_user = |id: usize, s: &UserSubmission| s.user == user && include(id);
+        add_scores(scores, user, &self.submissions, &self.current_level, self.level_start, &self.config, &by_user);
+    }
+
+    /// Starts evaluating every submission on the current level again,
+    /// optionally against a replacement level. The returned evaluations
+    /// should be run outside of the game lock and passed back to
+    /// `finish_evaluation`, scores are updated as they finish.
+    pub fn start_rejudge(&mut self, level: Option<contract::Level>) -> (Rejudge, Vec<PendingEvaluation>) {
+        if let Some(level) = level {
+            self.current_level = level;
+            self.play_sessions.clear();
+        }
+        // queued evaluations are redone here, workers should drop them
+        self.drop_unfinished();
+        self.generation += 1;
+        let previous_outcomes = self.submissions
+            .iter()
+            .map(|s| s.details.as_ref().map(|d| d.outcome))
+            .collect();
+        // previous results are kept until the new ones replace them
+        for submission in &mut self.submissions {
+            submission.status = contract::EvaluationStatus::Queued;
+            submission.rejudged = true;
+        }
+        let pending = (0..self.submissions.len())
+            .map(|id| self.pending_evaluation(None, id as u64))
+            .collect();
+        let rejudge = Rejudge {
+            generation: self.generation,
+            previous_outcomes,
+        };
+        (rejudge, pending)
+    }
+
+    /// Submissions whose outcome changed, once evaluations of the rejudge
+    /// are finished. Returns `None` if the rejudge was interrupted by a
+    /// level change or another rejudge.
+    pub fn rejudge_result(&self, rejudge: &Rejudge) -> Option<contract::RejudgeResult> {
+        if rejudge.generation != self.generation {
+            return None;
+        }
+        let changed = rejudge.previous_outcomes
+            .iter()
+            .enumerate()
+            .filter_map(|(id, &previous_outcome)| {
+                let submission = &self.submissions[id];
+                let outcome = submission.details.as_ref()?.outcome;
+                if submission.status != contract::EvaluationStatus::Done || previous_outcome == Some(outcome) {
+                    return None;
+                }
+                Some(contract::RejudgedSubmission {
+                    id: id as u64,
+                    user: submission.user.clone(),
+                    previous_outcome,
+                    outcome,
+                })
+            })
+            .collect();
+        Some(contract::RejudgeResult { changed })
+    }
+
+    /// Whether all evaluations of the rejudge were applied, or it was
+    /// interrupted.
+    pub fn is_rejudge_finished(&self, rejudge: &Rejudge) -> bool {
+        rejudge.generation != self.generation || !self.submissions.iter().any(|s| s.rejudged)
+    }
+
+    /// Rejudges right away, used when there is no worker pool.
+    pub fn rejudge(&mut self, level: Option<contract::Level>) -> contract::RejudgeResult {
+        let (rejudge, pending) = self.start_rejudge(level);
+        for pending in pending {
+            self.evaluate_now(pending);
+        }
+        self.rejudge_result(&rejudge).expect("rejudge can't be interrupted under the lock")
+    }
+
+    fn pending_submission(&self, generation: u64, past_level: Option<usize>, id: u64) -> Option<usize> {
+        if generation != self.generation {
+            return None;
+        }
+        let submissions = match past_level {
+            Some(level) => &self.previous_levels.get(level)?.submissions,
+            None => &self.submissions,
+        };
+        let index = id as usize;
+        match submissions.get(index) {
+            Some(s) if s.is_pending() => Some(index),
+            _ => None,
+        }
+    }
+
+    /// Starts (or restarts) manual play of the current level for the user.
+    /// Play sessions never affect the scoreboard.
+    pub fn start_play(&mut self, user: &str) -> contract::LevelState {
+        let simulation = Simulation::new(&self.current_level);
+        self.play_sessions.insert(user.to_owned(), simulation);
+        self.current_level.state.clone()
+    }
+
+    /// Moves user's pacman once. Returns `None` if the user has no play
+    /// session, sessions end when the game is over.
+    pub fn play_move(&mut self, user: &str, next_move: contract::Move) -> Option<contract::PlayStep> {
+        let max_steps = self.max_steps();
+        let simulation = self.play_sessions.get_mut(user)?;
+        let mut driver = next_move;
+        let step = simulation.step(&mut driver);
+        // same order as `evaluate_program`: a win on the last allowed step
+        // is still out of moves
+        let outcome = if simulation.steps_taken() >= max_steps {
+            Some(contract::Outcome::OutOfMoves)
+        } else {
+            simulation.outcome()
+        };
+        if outcome.is_some() {
+            self.play_sessions.remove(user);
+        }
+        Some(contract::PlayStep { step, outcome })
+    }
+
+    fn max_steps(&self) -> u64 {
+        self.current_level.max_steps.unwrap_or(self.config.max_steps)
+    }
+
     pub fn all_submissions(&self) -> contract::Submissions {
+        let level = self.level_index();
         contract::Submissions {
             submissions: self.submissions
                 .iter()
                 .enumerate()
-                .map(|(id, sub)| contract::Submission {
-                    id: id as u64,
-                    user: sub.user.clone(),
-                })
+                .map(|(id, sub)| sub.to_contract(level, id as u64))
                 .collect(),
+            total: self.submissions.len() as u64,
             level_closed: self.is_level_closed,
             level: self.current_level.state.clone(),
         }
     }
 
-    pub fn submission_details(&self, id: u64) -> Option<contract::SubmissionDetails> {
-        self.submissions.get(id as usize).map(|s| &s.details).cloned()
+    /// Replay and program of an evaluated submission. Returns `None` if
+    /// the visibility policy does not allow `viewer` to see it, or if its
+    /// result is hidden by a scoreboard freeze.
+    pub fn submission_details(&self, level: Option<u64>, id: u64, viewer: Option<&str>) -> Option<contract::SubmissionDetails> {
+        let submission = self.visible_submission(level, id, viewer)?;
+        let mut details = submission.details.clone()?;
+        details.program = Some(submission.program.clone());
+        Some(details)
+    }
+
+    /// Same visibility rules as `submission_details`.
+    pub fn submission_status(&self, level: Option<u64>, id: u64, viewer: Option<&str>) -> Option<contract::SubmissionStatus> {
+        let submission = self.visible_submission(level, id, viewer)?;
+        Some(contract::SubmissionStatus {
+            id,
+            status: submission.status,
+            outcome: submission.details.as_ref().map(|d| d.outcome),
+        })
+    }
+
+    fn visible_submission(&self, level: Option<u64>, id: u64, viewer: Option<&str>) -> Option<&UserSubmission> {
+        let past_level = self.past_level(level)?;
+        let submission = self.level_submissions(past_level).get(id as usize)?;
+        if !self.can_view(submission, past_level.is_some(), viewer) {
+            return None;
+        }
+        if past_level.is_none() && self.hidden_submissions(viewer).contains(&(id as usize)) {
+            return None;
+        }
+        Some(submission)
+    }
+
+    /// Past levels count as closed.
+    fn can_view(&self, submission: &UserSubmission, past: bool, viewer: Option<&str>) -> bool {
+        if viewer == Some(submission.user.as_str()) {
+            return true;
+        }
+        match self.config.visibility {
+            Visibility::Own => false,
+            Visibility::AfterClose => past || self.is_level_closed,
+            Visibility::Public => true,
+        }
+    }
+}
+
+/// Adds evaluated submissions on a level for which `include` returns true
+/// (given submission id) to the scoreboard under `name`, in the order they
+/// were submitted. Practice submissions never count.
+fn add_scores(scores: &mut Scoreboard, name: &str, submissions: &[UserSubmission], level: &contract::Level, start: DateTime<Utc>, config: &GameConfig, include: &dyn Fn(usize, &UserSubmission) -> bool) {
+    let attempt_penalty = config.wrong_attempt_penalty.map_or(0, |p| p.num_seconds());
+    let ranking = level_ranking(level, config);
+    let mut wrong_attempts = 0;
+    let mut solved = false;
+    let submissions = submissions
+        .iter()
+        .enumerate()
+        .filter(|&(id, s)| !s.practice && include(id, s));
+    for (id, submission) in submissions {
+        let details = match &submission.details {
+            Some(details) => details,
+            None => continue,
+        };
+        if details.outcome == contract::Outcome::Success {
+            let time_penalty = (submission.submitted_at - start).num_seconds()
+                + wrong_attempts * attempt_penalty;
+            scores.add_user_evaluation(
+                name,
+                id as u64,
+                time_penalty,
+                submission.program.rules.len(),
+                details.steps.len().saturating_sub(1),
+                ranking.as_ref(),
+            );
+            solved = true;
+        } else if !solved {
+            scores.add_wrong_attempt(name);
+            wrong_attempts += 1;
+        }
+    }
+}
+
+/// Rejects level settings that can't be ranked, i.e. weighted ranking
+/// without weights.
+pub fn validate_ranking(level: &contract::Level) -> Result<(), String> {
+    match &level.ranking {
+        Some(contract::Ranking { criterion: contract::RankingCriterion::Weighted, weights: None }) => {
+            Err("weighted ranking needs weights".to_owned())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Ranking of the level. Levels without one are ranked by steps, or by
+/// time if wrong attempts are penalized, as the penalty is added to time.
+fn level_ranking(level: &contract::Level, config: &GameConfig) -> Option<contract::Ranking> {
+    match (&level.ranking, config.wrong_attempt_penalty) {
+        (None, Some(_)) => Some(contract::Ranking {
+            criterion: contract::RankingCriterion::Time,
+            weights: None,
+        }),
+        (ranking, _) => ranking.clone(),
+    }
+}
+
+/// Whether time penalties (e.g. for wrong attempts) affect the ranking.
+fn ranks_by_time(ranking: Option<&contract::Ranking>) -> bool {
+    match ranking {
+        Some(contract::Ranking { criterion: contract::RankingCriterion::Time, .. }) => true,
+        Some(contract::Ranking { criterion: contract::RankingCriterion::Weighted, weights: Some(weights) }) => weights.time != 0.0,
+        _ => false,
     }
 }
 
@@ -178,5 +858,716 @@ fn empty_level() -> contract::Level {
         ghost_program: contract::Program {
             rules: Vec::new(),
         },
+        max_steps: None,
+        max_rules: None,
+        hidden_variants: Vec::new(),
+        ranking: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use contract::{Cell, DeathState, EvaluationStatus, Move, Object, ObjectKind, Outcome, Rule, RuleState, SubmitResponse};
+
+    fn object(id: u64, kind: ObjectKind, col: u64) -> Object {
+        Object {
+            id,
+            row: 1,
+            col,
+            current_move: Move::Wait,
+            intended_move: Move::Wait,
+            state: DeathState::Alive,
+            kind,
+        }
+    }
+
+    /// A single corridor with pacman on the left and a berry two cells to the right.
+    fn corridor_level() -> contract::Level {
+        let walls = vec![Cell::Wall; 5];
+        contract::Level {
+            state: contract::LevelState {
+                cells: vec![
+                    walls.clone(),
+                    vec![Cell::Wall, Cell::Empty, Cell::Empty, Cell::Empty, Cell::Wall],
+                    walls,
+                ],
+                objects: vec![
+                    object(0, ObjectKind::Pacman, 1),
+                    object(1, ObjectKind::Berry, 3),
+                ],
+            },
+            ghost_program: contract::Program { rules: Vec::new() },
+            max_steps: None,
+            max_rules: None,
+            hidden_variants: Vec::new(),
+            ranking: None,
+        }
+    }
+
+    fn go(next_move: Move) -> Rule {
+        Rule {
+            current_state: None,
+            up: None,
+            down: None,
+            left: None,
+            right: None,
+            berry: None,
+            next_move,
+            next_state: RuleState::A,
+        }
+    }
+
+    fn program(rules: Vec<Rule>) -> contract::Program {
+        contract::Program { rules }
+    }
+
+    /// Users who only failed are listed too.
+    fn solved_count(scoreboard: &contract::Scoreboard) -> usize {
+        scoreboard.entries.iter().filter(|e| e.solved > 0).count()
+    }
+
+    fn config() -> GameConfig {
+        GameConfig {
+            max_steps: 100,
+            rate_limit: RateLimit {
+                count: 100,
+                window: Duration::seconds(1),
+            },
+            max_queued: 10,
+            visibility: Visibility::AfterClose,
+            sandbox_max_steps: 100,
+            sandbox_rate_limit: RateLimit {
+                count: 100,
+                window: Duration::seconds(1),
+            },
+            wrong_attempt_penalty: None,
+            scoreboard_freeze: None,
+            manual_unfreeze: false,
+            teams: HashMap::new(),
+            team_rate_limit: false,
+        }
+    }
+
+    fn time(seconds: i64) -> DateTime<Utc> {
+        Utc.timestamp(1_000_000 + seconds, 0)
+    }
+
+    #[test]
+    fn solves_corridor() {
+        let mut game = PacmanGame::new(config());
+        game.set_level(corridor_level(), time(0));
+        let response = game.submit_program("user", &program(vec![go(Move::Right)]), time(1));
+        assert_eq!(response, SubmitResponse::Ok);
+        let details = game.submission_details(None, 0, Some("user")).unwrap();
+        assert_eq!(details.outcome, Outcome::Success);
+        assert_eq!(details.steps.len(), 3);
+    }
+
+    #[test]
+    fn level_step_limit_overrides_config() {
+        let mut game = PacmanGame::new(config());
+        let mut level = corridor_level();
+        level.max_steps = Some(2);
+        game.set_level(level, time(0));
+        game.submit_program("user", &program(vec![go(Move::Right)]), time(1));
+        let details = game.submission_details(None, 0, Some("user")).unwrap();
+        assert_eq!(details.outcome, Outcome::OutOfMoves);
+    }
+
+    #[test]
+    fn rejects_too_many_rules() {
+        let mut game = PacmanGame::new(config());
+        let mut level = corridor_level();
+        level.max_rules = Some(1);
+        game.set_level(level, time(0));
+        let long = program(vec![go(Move::Right), go(Move::Left)]);
+        assert_eq!(game.submit_program("user", &long, time(1)), SubmitResponse::TooManyRules);
+        assert!(game.submission_details(None, 0, Some("user")).is_none());
+        let short = program(vec![go(Move::Right)]);
+        assert_eq!(game.submit_program("user", &short, time(2)), SubmitResponse::Ok);
+    }
+
+    #[test]
+    fn queued_submission_is_scored_when_finished() {
+        let mut game = PacmanGame::new(config());
+        game.set_level(corridor_level(), time(0));
+        let pending = game.queue_program("user", &program(vec![go(Move::Right)]), time(5)).unwrap();
+        assert_eq!(game.submission_status(None, 0, Some("user")).unwrap().status, EvaluationStatus::Queued);
+        assert!(game.submission_details(None, 0, Some("user")).is_none());
+        assert!(game.start_evaluation(&pending));
+        assert_eq!(game.submission_status(None, 0, Some("user")).unwrap().status, EvaluationStatus::Running);
+        game.finish_evaluation(pending.evaluate());
+        let status = game.submission_status(None, 0, Some("user")).unwrap();
+        assert_eq!(status.status, EvaluationStatus::Done);
+        assert_eq!(status.outcome, Some(Outcome::Success));
+        assert_eq!(game.get_scores().scoreboards[0].entries.len(), 1);
+        assert!(game.submission_status(None, 0, Some("other")).is_none());
+        assert!(game.submission_status(None, 0, None).is_none());
+    }
+
+    #[test]
+    fn stale_evaluation_is_dropped() {
+        let mut game = PacmanGame::new(config());
+        game.set_level(corridor_level(), time(0));
+        let pending = game.queue_program("user", &program(vec![go(Move::Right)]), time(5)).unwrap();
+        game.set_level(corridor_level(), time(10));
+        game.queue_program("other", &program(Vec::new()), time(11)).unwrap();
+        assert!(!game.start_evaluation(&pending));
+        game.finish_evaluation(pending.evaluate());
+        assert_eq!(game.submission_status(None, 0, Some("other")).unwrap().status, EvaluationStatus::Queued);
+        assert!(game.get_scores().scoreboards[0].entries.is_empty());
+        assert_eq!(game.level_submissions(Some(1))[0].status, EvaluationStatus::Dropped);
+        game.undo_level_change(1);
+        assert_eq!(game.submission_status(None, 0, Some("user")).unwrap().status, EvaluationStatus::Queued);
+        game.set_level(corridor_level(), time(20));
+        assert_eq!(game.level_submissions(Some(1))[0].status, EvaluationStatus::Dropped);
+    }
+
+    #[test]
+    fn queue_is_bounded() {
+        let mut game = PacmanGame::new(config());
+        game.set_level(corridor_level(), time(0));
+        for i in 0..10 {
+            assert!(game.queue_program("user", &program(Vec::new()), time(i)).is_ok());
+        }
+        let response = game.queue_program("user", &program(Vec::new()), time(10)).err();
+        assert_eq!(response, Some(SubmitResponse::QueueFull));
+        // evaluations queued before a level change are never applied
+        game.set_level(corridor_level(), time(20));
+        assert!(game.queue_program("user", &program(Vec::new()), time(20)).is_ok());
+    }
+
+    #[test]
+    fn simulation_steps_and_restores() {
+        let mut simulation = Simulation::new(&corridor_level());
+        let snapshot = simulation.snapshot();
+        simulation.step(&mut Move::Right);
+        simulation.step(&mut Move::Right);
+        let berry = simulation.objects().find(|o| o.kind == ObjectKind::Berry).unwrap();
+        assert_eq!(berry.state, DeathState::DiesAtEnd);
+        assert_eq!(simulation.outcome(), None);
+        simulation.step(&mut Move::Wait);
+        assert_eq!(simulation.outcome(), Some(Outcome::Success));
+        simulation.restore(&snapshot);
+        assert_eq!(simulation.steps_taken(), 0);
+        let pacman = simulation.objects().find(|o| o.kind == ObjectKind::Pacman).unwrap();
+        assert_eq!(pacman.col, 1);
+        assert_eq!(simulation.rule_state(pacman.id), Some(RuleState::A));
+    }
+
+    #[test]
+    fn play_session_ends_with_outcome() {
+        let mut game = PacmanGame::new(config());
+        game.set_level(corridor_level(), time(0));
+        assert!(game.play_move("user", Move::Right).is_none());
+        game.start_play("user");
+        assert_eq!(game.play_move("user", Move::Right).unwrap().outcome, None);
+        assert_eq!(game.play_move("user", Move::Right).unwrap().outcome, None);
+        let last = game.play_move("user", Move::Wait).unwrap();
+        assert_eq!(last.outcome, Some(Outcome::Success));
+        assert!(game.play_move("user", Move::Wait).is_none());
+        assert!(game.get_scores().scoreboards[0].entries.is_empty());
+    }
+
+    #[test]
+    fn play_agrees_with_evaluation_on_step_limit() {
+        let mut level = corridor_level();
+        level.max_steps = Some(3);
+        let mut game = PacmanGame::new(config());
+        game.set_level(level, time(0));
+        game.start_play("user");
+        game.play_move("user", Move::Right);
+        game.play_move("user", Move::Right);
+        let last = game.play_move("user", Move::Right).unwrap();
+        game.submit_program("user", &program(vec![go(Move::Right)]), time(1));
+        let submitted = game.submission_status(None, 0, Some("user")).unwrap().outcome;
+        assert_eq!(last.outcome, Some(Outcome::OutOfMoves));
+        assert_eq!(last.outcome, submitted);
+    }
+
+    #[test]
+    fn journal_replay_rebuilds_game() {
+        use journal::Event;
+        let events = [
+            Event::SetLevel { level: corridor_level(), time: time(0) },
+            Event::Submit { user: "a".to_owned(), program: program(vec![go(Move::Right)]), time: time(30) },
+            Event::Submit { user: "b".to_owned(), program: program(vec![go(Move::Left)]), time: time(40) },
+            Event::Evaluated { past_level: None, id: 1 },
+            Event::Evaluated { past_level: None, id: 0 },
+            Event::SetLevelState { closed: true },
+        ];
+        let json = events
+            .iter()
+            .map(|e| serde_json::to_string(e).unwrap() + "\n")
+            .collect::<String>();
+        let mut game = PacmanGame::new(config());
+        for event in journal::read_events(&json).unwrap() {
+            game.apply(&event);
+        }
+        let submissions = game.all_submissions();
+        assert_eq!(submissions.submissions.len(), 2);
+        assert!(submissions.level_closed);
+        let results = &game.get_scores().scoreboards[0];
+        assert_eq!(solved_count(results), 1);
+        assert_eq!(results.entries[0].user, "a");
+    }
+
+    #[test]
+    fn journal_replay_drops_evaluations_like_live_game() {
+        use journal::Event;
+        let mut live = PacmanGame::new(config());
+        let mut events = vec![Event::SetLevel { level: corridor_level(), time: time(0) }];
+        live.set_level(corridor_level(), time(0));
+        let solution = program(vec![go(Move::Right)]);
+        let evaluated = live.queue_program("a", &solution, time(10)).unwrap();
+        events.push(Event::Submit { user: "a".to_owned(), program: solution.clone(), time: time(10) });
+        let stale = live.queue_program("b", &solution, time(20)).unwrap();
+        events.push(Event::Submit { user: "b".to_owned(), program: solution.clone(), time: time(20) });
+        let finished = evaluated.evaluate();
+        events.push(finished.event());
+        assert!(live.finish_evaluation(finished));
+        live.set_level(corridor_level(), time(30));
+        events.push(Event::SetLevel { level: corridor_level(), time: time(30) });
+        assert!(!live.finish_evaluation(stale.evaluate()));
+
+        let mut replayed = PacmanGame::new(config());
+        for event in &events {
+            replayed.apply(event);
+        }
+        for game in &[live, replayed] {
+            let totals = &game.get_scores().scoreboards[1];
+            assert_eq!(totals.entries.len(), 1);
+            assert_eq!(totals.entries[0].user, "a");
+            assert!(game.pending_evaluations().is_empty());
+        }
+    }
+
+    #[test]
+    fn snapshot_roundtrip() {
+        let mut game = PacmanGame::new(config());
+        game.set_level(corridor_level(), time(0));
+        game.submit_program("a", &program(vec![go(Move::Right)]), time(30));
+        game.rate_limit_user("b", RateLimit { count: 1, window: Duration::seconds(60) });
+        game.queue_program("b", &program(vec![go(Move::Right)]), time(40)).unwrap();
+        game.set_level_state(true);
+        let raw = game.raw_snapshot();
+
+        let mut restored = PacmanGame::new(config());
+        let pending = restored.import_snapshot(&raw).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(restored.all_submissions().submissions.len(), 2);
+        assert!(restored.all_submissions().level_closed);
+        for pending in pending {
+            restored.evaluate_now(pending);
+        }
+        assert_eq!(restored.get_scores().scoreboards[0].entries.len(), 2);
+        restored.set_level_state(false);
+        let response = restored.queue_program("b", &program(Vec::new()), time(50)).err();
+        assert_eq!(response, Some(SubmitResponse::RateLimitExceeded));
+    }
+
+    #[test]
+    fn score_dump_continues_level_indexes() {
+        let solution = program(vec![go(Move::Right)]);
+        let mut game = PacmanGame::new(config());
+        game.set_level(corridor_level(), time(0));
+        game.submit_program("a", &solution, time(30));
+        game.set_level(corridor_level(), time(100));
+
+        let mut continued = PacmanGame::from_raw_scoreboard(config(), &game.raw_scoreboard()).unwrap();
+        continued.set_level(corridor_level(), time(200));
+        continued.submit_program("b", &solution, time(230));
+        assert_eq!(continued.all_submissions().submissions[0].level, 3);
+        assert!(continued.submission_status(Some(3), 0, Some("b")).is_some());
+        assert!(continued.submission_status(Some(1), 0, Some("b")).is_none());
+        let mut restored = PacmanGame::new(config());
+        restored.import_snapshot(&continued.raw_snapshot()).unwrap();
+        restored.set_level(corridor_level(), time(300));
+
+        let scores = restored.get_scores();
+        let mut levels = scores.scoreboards[1].entries
+            .iter()
+            .map(|e| e.levels.iter().flatten().map(|l| l.level).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        levels.sort();
+        assert_eq!(levels, vec![vec![1], vec![3]]);
+    }
+
+    #[test]
+    fn undo_restores_previous_level() {
+        let mut game = PacmanGame::new(config());
+        assert!(game.undo_level_change(1).is_none());
+        game.set_level(corridor_level(), time(0));
+        game.submit_program("a", &program(vec![go(Move::Right)]), time(30));
+        game.set_level_state(true);
+        game.set_level(corridor_level(), time(100));
+        game.submit_program("b", &program(vec![go(Move::Right)]), time(130));
+        game.set_level(corridor_level(), time(200));
+        assert_eq!(game.get_scores().scoreboards[1].entries.len(), 2);
+
+        assert!(game.undo_level_change(4).is_none());
+        let pending = game.undo_level_change(2).unwrap();
+        assert!(pending.is_empty());
+        let submissions = game.all_submissions();
+        assert_eq!(submissions.submissions.len(), 1);
+        assert_eq!(submissions.submissions[0].user, "a");
+        assert!(submissions.level_closed);
+        let scores = game.get_scores();
+        assert_eq!(scores.scoreboards[0].entries.len(), 1);
+        assert_eq!(scores.scoreboards[1].entries.len(), 1);
+    }
+
+    #[test]
+    fn submissions_are_shown_to_author_until_closed() {
+        let mut game = PacmanGame::new(config());
+        game.set_level(corridor_level(), time(0));
+        game.submit_program("a", &program(vec![go(Move::Right)]), time(10));
+        game.submit_program("b", &program(vec![go(Move::Right)]), time(20));
+        assert!(game.submission_details(None, 0, None).is_none());
+        assert!(game.submission_details(None, 0, Some("b")).is_none());
+        let details = game.submission_details(None, 0, Some("a")).unwrap();
+        assert_eq!(details.program.unwrap().rules[0].next_move, Move::Right);
+        let listed = game.query_submissions(&Default::default(), Some("b"));
+        assert_eq!(listed.total, 1);
+        assert_eq!(listed.submissions[0].user, "b");
+
+        game.set_level_state(true);
+        assert!(game.submission_details(None, 0, None).is_some());
+        assert_eq!(game.query_submissions(&Default::default(), None).total, 2);
+    }
+
+    #[test]
+    fn own_visibility_hides_other_users_after_close() {
+        let mut config = config();
+        config.visibility = Visibility::Own;
+        let mut game = PacmanGame::new(config);
+        game.set_level(corridor_level(), time(0));
+        game.submit_program("a", &program(vec![go(Move::Right)]), time(10));
+        game.set_level_state(true);
+        assert!(game.submission_details(None, 0, Some("b")).is_none());
+        assert!(game.submission_details(None, 0, Some("a")).is_some());
+    }
+
+    #[test]
+    fn submissions_are_filtered_and_paged() {
+        let mut game = PacmanGame::new(config());
+        game.set_level(corridor_level(), time(0));
+        game.submit_program("a", &program(vec![go(Move::Right)]), time(10));
+        game.submit_program("b", &program(vec![go(Move::Right)]), time(20));
+        game.submit_program("a", &program(vec![go(Move::Left), go(Move::Up)]), time(30));
+        game.set_level(corridor_level(), time(100));
+        game.submit_program("a", &program(vec![go(Move::Right)]), time(110));
+
+        let query = contract::SubmissionsQuery {
+            user: Some("a".to_owned()),
+            ..Default::default()
+        };
+        let submissions = game.query_submissions(&query, Some("a"));
+        assert_eq!(submissions.total, 1);
+        assert_eq!(submissions.submissions[0].level, 2);
+        assert_eq!(submissions.submissions[0].step_count, Some(2));
+
+        let query = contract::SubmissionsQuery {
+            order: Some(contract::SubmissionOrder::Newest),
+            limit: Some(2),
+            ..Default::default()
+        };
+        let history = game.user_submissions("a", &query);
+        assert_eq!(history.total, 3);
+        let ids = history.submissions.iter().map(|s| (s.level, s.id)).collect::<Vec<_>>();
+        assert_eq!(ids, vec![(2, 0), (1, 2)]);
+        assert_eq!(history.submissions[1].rule_count, 2);
+        assert_eq!(history.submissions[1].outcome, Some(Outcome::OutOfMoves));
+
+        let query = contract::SubmissionsQuery {
+            outcome: Some(Outcome::Success),
+            offset: Some(1),
+            ..Default::default()
+        };
+        let history = game.user_submissions("a", &query);
+        assert_eq!(history.total, 2);
+        assert_eq!(history.submissions.len(), 1);
+        assert_eq!(history.submissions[0].level, 2);
+    }
+
+    #[test]
+    fn practice_does_not_affect_scores() {
+        let mut game = PacmanGame::new(config());
+        game.set_level(corridor_level(), time(0));
+        game.set_level_state(true);
+        let solution = program(vec![go(Move::Right)]);
+        assert_eq!(game.submit_program("a", &solution, time(10)), SubmitResponse::LevelClosed);
+        let pending = game.queue_practice("a", &solution, None, time(10)).unwrap();
+        game.evaluate_now(pending);
+        assert_eq!(game.submission_status(None, 0, Some("a")).unwrap().outcome, Some(Outcome::Success));
+        assert!(game.all_submissions().submissions[0].practice);
+
+        game.set_level(corridor_level(), time(100));
+        let pending = game.queue_practice("b", &solution, Some(1), time(110)).unwrap();
+        assert_eq!((pending.level_index, pending.id), (1, 1));
+        game.evaluate_now(pending);
+        assert_eq!(game.submission_status(Some(1), 1, Some("b")).unwrap().outcome, Some(Outcome::Success));
+        assert!(game.submission_details(Some(1), 1, Some("b")).is_some());
+        assert!(game.submission_status(None, 1, Some("b")).is_none());
+        assert!(game.submission_status(Some(3), 1, Some("b")).is_none());
+        let response = game.queue_practice("b", &solution, Some(3), time(120)).err();
+        assert_eq!(response, Some(SubmitResponse::UnknownLevel));
+        let history = game.user_submissions("b", &Default::default());
+        assert_eq!(history.submissions.len(), 1);
+        assert_eq!(history.submissions[0].level, 1);
+        assert_eq!(history.submissions[0].outcome, Some(Outcome::Success));
+
+        game.rejudge(None);
+        let scores = game.get_scores();
+        assert!(scores.scoreboards[0].entries.is_empty());
+        assert!(scores.scoreboards[1].entries.is_empty());
+    }
+
+    #[test]
+    fn hidden_variants_must_be_won() {
+        let mut level = corridor_level();
+        let mut variant = level.state.clone();
+        variant.objects[0].col = 3;
+        variant.objects[1].col = 1;
+        level.hidden_variants = vec![variant];
+        let mut game = PacmanGame::new(config());
+        game.set_level(level, time(0));
+        game.submit_program("a", &program(vec![go(Move::Right)]), time(10));
+        let details = game.submission_details(None, 0, Some("a")).unwrap();
+        assert_eq!(details.outcome, Outcome::OutOfMoves);
+        assert_eq!(details.hidden_outcomes, vec![Outcome::OutOfMoves]);
+        assert_eq!(details.steps.len(), 3);
+        assert_eq!(solved_count(&game.get_scores().scoreboards[0]), 0);
+        assert_eq!(game.all_submissions().level.objects[0].col, 1);
+    }
+
+    #[test]
+    fn wrong_attempts_add_penalty() {
+        let mut config = config();
+        config.wrong_attempt_penalty = Some(Duration::seconds(1200));
+        let mut game = PacmanGame::new(config);
+        // ranked by time, as the level doesn't say otherwise
+        game.set_level(corridor_level(), time(0));
+        let wrong = program(vec![go(Move::Left)]);
+        let right = program(vec![go(Move::Right)]);
+        game.submit_program("a", &wrong, time(10));
+        game.submit_program("b", &wrong, time(20));
+        let late = game.queue_program("a", &wrong, time(30)).unwrap();
+        game.submit_program("a", &right, time(60));
+        game.submit_program("a", &wrong, time(90));
+        // finishes after the success, but was submitted before it
+        game.evaluate_now(late);
+
+        let results = &game.get_scores().scoreboards[0];
+        assert_eq!(results.entries.len(), 2);
+        assert_eq!(results.entries[0].user, "a");
+        assert_eq!(results.entries[0].attempts, 3);
+        assert_eq!(results.entries[0].tie_breaker, "41:00");
+        assert_eq!(results.entries[0].best_submissions.as_ref().unwrap().time, 3);
+        // listed with their attempts, even though nothing was solved
+        let unsolved = &results.entries[1];
+        assert_eq!((unsolved.user.as_str(), unsolved.rank, unsolved.solved), ("b", 2, 0));
+        assert_eq!((unsolved.attempts, unsolved.tie_breaker.as_str()), (1, "-"));
+    }
+
+    #[test]
+    fn rejudge_against_replacement_level() {
+        let mut game = PacmanGame::new(config());
+        game.set_level(corridor_level(), time(0));
+        game.submit_program("a", &program(vec![go(Move::Right)]), time(10));
+        game.submit_program("b", &program(vec![go(Move::Left)]), time(20));
+        let pending = game.queue_program("c", &program(vec![go(Move::Right)]), time(30)).unwrap();
+        assert_eq!(solved_count(&game.get_scores().scoreboards[0]), 1);
+
+        let mut level = corridor_level();
+        level.max_steps = Some(1);
+        let result = game.rejudge(Some(level));
+        let changed = result.changed
+            .iter()
+            .map(|c| (c.user.as_str(), c.previous_outcome, c.outcome))
+            .collect::<Vec<_>>();
+        assert_eq!(changed, vec![
+            ("a", Some(Outcome::Success), Outcome::OutOfMoves),
+            ("c", None, Outcome::OutOfMoves),
+        ]);
+        assert_eq!(solved_count(&game.get_scores().scoreboards[0]), 0);
+        assert!(!game.start_evaluation(&pending));
+
+        let result = game.rejudge(Some(corridor_level()));
+        assert_eq!(result.changed.len(), 2);
+        assert_eq!(solved_count(&game.get_scores().scoreboards[0]), 2);
+    }
+
+    #[test]
+    fn rejudge_is_applied_as_evaluations_finish() {
+        let mut game = PacmanGame::new(config());
+        game.set_level(corridor_level(), time(0));
+        game.submit_program("a", &program(vec![go(Move::Right)]), time(10));
+        let mut level = corridor_level();
+        level.max_steps = Some(1);
+        let (rejudge, mut pending) = game.start_rejudge(Some(level));
+        assert_eq!(pending.len(), 1);
+        assert!(!game.is_rejudge_finished(&rejudge));
+        assert_eq!(game.get_scores().scoreboards[0].entries.len(), 1);
+        // rejudged submissions don't fill up the queue
+        let mut config = config();
+        config.max_queued = 1;
+        game.set_config(config);
+        assert!(game.queue_program("b", &program(Vec::new()), time(20)).is_ok());
+        assert!(game.start_evaluation(&pending[0]));
+        game.finish_evaluation(pending.remove(0).evaluate());
+        assert!(game.is_rejudge_finished(&rejudge));
+        assert_eq!(solved_count(&game.get_scores().scoreboards[0]), 0);
+        assert_eq!(game.rejudge_result(&rejudge).unwrap().changed.len(), 1);
+
+        let (rejudge, pending) = game.start_rejudge(None);
+        game.set_level(corridor_level(), time(100));
+        assert!(game.is_rejudge_finished(&rejudge));
+        assert!(!game.start_evaluation(&pending[0]));
+        assert!(game.rejudge_result(&rejudge).is_none());
+    }
+
+    #[test]
+    fn schedule_opens_and_closes_levels() {
+        let scheduled = |opens_at: i64| contract::ScheduledLevel {
+            level: corridor_level(),
+            opens_at: time(opens_at).to_rfc3339(),
+            duration: 60,
+        };
+        let schedule = contract::Schedule {
+            levels: vec![scheduled(0), scheduled(100), scheduled(200)],
+        };
+        let mut game = PacmanGame::new(config());
+        let schedule = schedule::Schedule::from_contract(&schedule, time(70)).unwrap();
+        game.set_schedule(schedule, time(70));
+        assert_eq!(game.schedule_info(time(70)).current, None);
+        assert_eq!(game.schedule_info(time(70)).seconds_until_change, Some(30));
+        assert!(!game.advance_schedule(time(70)));
+        assert!(game.all_submissions().level_closed);
+
+        assert!(game.advance_schedule(time(100)));
+        assert!(!game.all_submissions().level_closed);
+        assert_eq!(game.schedule_info(time(100)).current, Some(1));
+        game.submit_program("a", &program(vec![go(Move::Right)]), time(110));
+        assert!(!game.advance_schedule(time(159)));
+        assert!(game.advance_schedule(time(160)));
+        assert!(game.all_submissions().level_closed);
+        assert_eq!(game.schedule_info(time(160)).current, None);
+
+        assert!(game.advance_schedule(time(500)));
+        assert_eq!(game.schedule_info(time(500)).seconds_until_change, None);
+        assert!(game.all_submissions().level_closed);
+        assert_eq!(game.get_scores().scoreboards[1].entries.len(), 1);
+    }
+
+    #[test]
+    fn schedule_keeps_track_of_open_level() {
+        let scheduled = |opens_at: i64| contract::ScheduledLevel {
+            level: corridor_level(),
+            opens_at: time(opens_at).to_rfc3339(),
+            duration: 60,
+        };
+        let schedule = |levels| schedule::Schedule::from_contract(&contract::Schedule { levels }, time(10)).unwrap();
+        let mut game = PacmanGame::new(config());
+        game.set_schedule(schedule(vec![scheduled(0)]), time(10));
+        assert!(game.advance_schedule(time(10)));
+        game.set_schedule(schedule(vec![scheduled(5), scheduled(100)]), time(20));
+        assert_eq!(game.schedule_info(time(20)).current, Some(0));
+        assert!(!game.advance_schedule(time(20)));
+        assert!(game.advance_schedule(time(65)));
+        assert!(game.all_submissions().level_closed);
+
+        assert!(game.advance_schedule(time(100)));
+        assert_eq!(game.schedule_info(time(100)).current, Some(1));
+        game.undo_level_change(1).unwrap();
+        assert_eq!(game.schedule_info(time(100)).current, None);
+        assert!(game.all_submissions().level_closed);
+        assert!(game.advance_schedule(time(110)));
+        assert_eq!(game.schedule_info(time(110)).current, Some(1));
+    }
+
+    #[test]
+    fn frozen_scoreboard_hides_late_results() {
+        let mut config = config();
+        config.manual_unfreeze = true;
+        let mut game = PacmanGame::new(config);
+        game.set_level(corridor_level(), time(0));
+        let users = |scores: &contract::Scoreboards| {
+            let mut users = scores.scoreboards[0].entries
+                .iter()
+                .map(|e| e.user.clone())
+                .collect::<Vec<_>>();
+            users.sort();
+            users
+        };
+        game.submit_program("a", &program(vec![go(Move::Right)]), time(10));
+        game.freeze_scoreboard(time(20));
+        game.submit_program("b", &program(vec![go(Move::Right)]), time(30));
+        game.submit_program("c", &program(vec![go(Move::Right)]), time(40));
+
+        let public = game.get_scores_for(None);
+        assert!(public.frozen);
+        assert_eq!(users(&public), vec!["a"]);
+        assert_eq!(users(&game.get_scores_for(Some("c"))), vec!["a", "c"]);
+        assert_eq!(users(&game.get_scores()).len(), 3);
+        assert!(!game.get_scores().frozen);
+
+        game.set_level_state(true);
+        assert!(game.get_scores_for(None).frozen);
+        // other endpoints don't give hidden results away either
+        let outcomes = |game: &PacmanGame| {
+            game.query_submissions(&Default::default(), None)
+                .submissions
+                .iter()
+                .map(|s| s.outcome.is_some())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(outcomes(&game), vec![true, false, false]);
+        assert!(game.submission_status(None, 1, None).is_none());
+        assert!(game.submission_details(None, 1, Some("c")).is_none());
+        assert!(game.submission_status(None, 1, Some("b")).is_some());
+        assert!(game.submission_details(None, 0, None).is_some());
+        assert_eq!(game.unfreeze(1), 1);
+        assert_eq!(users(&game.get_scores_for(None)), vec!["a", "b"]);
+        assert_eq!(outcomes(&game), vec![true, true, false]);
+        assert!(game.submission_status(None, 1, None).is_some());
+        assert_eq!(game.unfreeze(5), 0);
+        assert!(!game.get_scores_for(None).frozen);
+        assert_eq!(users(&game.get_scores_for(None)).len(), 3);
+    }
+
+    #[test]
+    fn teams_share_best_results() {
+        let mut config = config();
+        config.teams.insert("a".to_owned(), "red".to_owned());
+        config.teams.insert("b".to_owned(), "red".to_owned());
+        config.teams.insert("c".to_owned(), "blue".to_owned());
+        config.team_rate_limit = true;
+        config.rate_limit.count = 2;
+        let mut game = PacmanGame::new(config);
+        game.set_level(corridor_level(), time(0));
+        game.submit_program("a", &program(vec![go(Move::Left)]), time(10));
+        game.submit_program("b", &program(vec![go(Move::Right)]), time(10));
+        assert_eq!(
+            game.submit_program("a", &program(vec![go(Move::Right)]), time(10)),
+            contract::SubmitResponse::RateLimitExceeded,
+        );
+        game.submit_program("c", &program(vec![go(Move::Right)]), time(10));
+
+        let scores = game.get_scores();
+        assert_eq!(scores.scoreboards.len(), 4);
+        assert_eq!(scores.scoreboards[0].entries.len(), 3);
+        assert_eq!(solved_count(&scores.scoreboards[0]), 2);
+        let teams = &scores.scoreboards[2].entries;
+        assert_eq!(teams.len(), 2);
+        let red = teams.iter().find(|e| e.user == "red").unwrap();
+        assert_eq!(red.attempts, 2);
+        assert_eq!(red.best_submissions.as_ref().unwrap().steps, 1);
+
+        game.set_level(corridor_level(), time(100));
+        let scores = game.get_scores();
+        assert!(scores.scoreboards[2].entries.is_empty());
+        assert_eq!(scores.scoreboards[3].entries.len(), 2);
     }
 }
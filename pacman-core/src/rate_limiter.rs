@@ -1,10 +1,15 @@
 use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use crate::time_format;
 
 pub struct RateLimitExceeded;
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct RateLimiter {
     max_submissions: usize,
+    #[serde(with = "time_format::millis")]
     time_window: Duration,
+    #[serde(with = "time_format::list")]
     entries: Vec<DateTime<Utc>>,
 }
 
@@ -0,0 +1,128 @@
+use std::collections::HashSet;
+use crate::contract::{CompactSubmissionDetails, Object, Step, StepDelta, SubmissionDetails};
+
+/// Encodes each step as a difference from the previous one (or from the
+/// initial state for the first step).
+pub fn compress(details: &SubmissionDetails) -> CompactSubmissionDetails {
+    let mut previous = &details.initial_state.objects;
+    let mut steps = Vec::with_capacity(details.steps.len());
+    for step in &details.steps {
+        steps.push(diff(previous, &step.objects));
+        previous = &step.objects;
+    }
+    CompactSubmissionDetails {
+        initial_state: details.initial_state.clone(),
+        steps,
+        outcome: details.outcome,
+        hidden_outcomes: details.hidden_outcomes.clone(),
+        program: details.program.clone(),
+    }
+}
+
+/// Reconstructs full steps from a compact replay.
+pub fn decompress(compact: &CompactSubmissionDetails) -> SubmissionDetails {
+    let mut objects = compact.initial_state.objects.clone();
+    let mut steps = Vec::with_capacity(compact.steps.len());
+    for delta in &compact.steps {
+        apply(&mut objects, delta);
+        steps.push(Step { objects: objects.clone() });
+    }
+    SubmissionDetails {
+        initial_state: compact.initial_state.clone(),
+        steps,
+        outcome: compact.outcome,
+        hidden_outcomes: compact.hidden_outcomes.clone(),
+        program: compact.program.clone(),
+    }
+}
+
+fn diff(previous: &[Object], current: &[Object]) -> StepDelta {
+    let current_ids = current.iter().map(|o| o.id).collect::<HashSet<_>>();
+    let removed = previous
+        .iter()
+        .map(|o| o.id)
+        .filter(|id| !current_ids.contains(id))
+        .collect();
+    let changed = current
+        .iter()
+        .filter(|obj| !previous.iter().any(|prev| prev == *obj))
+        .cloned()
+        .collect();
+    StepDelta { changed, removed }
+}
+
+fn apply(objects: &mut Vec<Object>, delta: &StepDelta) {
+    objects.retain(|o| !delta.removed.contains(&o.id));
+    for obj in &delta.changed {
+        match objects.iter_mut().find(|o| o.id == obj.id) {
+            Some(existing) => *existing = obj.clone(),
+            None => objects.push(obj.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contract::{Cell, DeathState, LevelState, Move, ObjectKind, Outcome};
+
+    fn object(id: u64, kind: ObjectKind, col: u64, state: DeathState) -> Object {
+        Object {
+            id,
+            row: 0,
+            col,
+            current_move: Move::Right,
+            intended_move: Move::Right,
+            state,
+            kind,
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let details = SubmissionDetails {
+            initial_state: LevelState {
+                cells: vec![vec![Cell::Empty; 4]],
+                objects: vec![
+                    object(0, ObjectKind::Pacman, 0, DeathState::Alive),
+                    object(1, ObjectKind::Ghost, 3, DeathState::Alive),
+                    object(2, ObjectKind::Berry, 2, DeathState::Alive),
+                ],
+            },
+            steps: vec![
+                Step {
+                    objects: vec![
+                        object(0, ObjectKind::Pacman, 1, DeathState::Alive),
+                        object(1, ObjectKind::Ghost, 3, DeathState::Alive),
+                        object(2, ObjectKind::Berry, 2, DeathState::Alive),
+                    ],
+                },
+                Step {
+                    objects: vec![
+                        object(0, ObjectKind::Pacman, 2, DeathState::Alive),
+                        object(1, ObjectKind::Ghost, 3, DeathState::Alive),
+                        object(2, ObjectKind::Berry, 2, DeathState::DiesAtEnd),
+                    ],
+                },
+                Step {
+                    objects: vec![
+                        object(0, ObjectKind::Pacman, 3, DeathState::Alive),
+                        object(1, ObjectKind::Ghost, 3, DeathState::DiesAtEnd),
+                    ],
+                },
+            ],
+            outcome: Outcome::Success,
+            hidden_outcomes: Vec::new(),
+            program: None,
+        };
+        let compact = compress(&details);
+        assert_eq!(compact.steps[0].changed.len(), 1);
+        assert!(compact.steps[0].removed.is_empty());
+        assert_eq!(compact.steps[2].removed, vec![2]);
+        let decoded = decompress(&compact);
+        assert_eq!(decoded.steps.len(), details.steps.len());
+        for (decoded, original) in decoded.steps.iter().zip(&details.steps) {
+            assert_eq!(decoded.objects, original.objects);
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::cmp;
+use std::collections::HashSet;
+use chrono::{DateTime, Utc};
+use crate::{contract, evaluator, validate_ranking, PacmanGame};
+use crate::rate_limiter::RateLimiter;
+
+// Limits are a few times what the bundled levels use (at most 15x15 cells,
+// 13 objects and 48 ghost rules), evaluation cost grows with rules times
+// objects squared.
+/// Max number of rows and columns.
+const MAX_SIZE: usize = 32;
+const MAX_OBJECTS: usize = 32;
+/// Max number of rules in both ghost and pacman programs.
+const MAX_RULES: usize = 64;
+const MAX_VARIANTS: usize = 4;
+
+/// Checks that a user supplied level is well formed and small enough to be
+/// evaluated.
+pub fn validate_level(level: &contract::Level) -> Result<(), String> {
+    if level.ghost_program.rules.len() > MAX_RULES {
+        return Err(format!("ghost program has more than {} rules", MAX_RULES));
+    }
+    if level.hidden_variants.len() > MAX_VARIANTS {
+        return Err(format!("level has more than {} hidden variants", MAX_VARIANTS));
+    }
+    validate_ranking(level)?;
+    validate_map(&level.state)?;
+    for (index, variant) in level.hidden_variants.iter().enumerate() {
+        validate_map(variant).map_err(|e| format!("hidden variant {}: {}", index, e))?;
+    }
+    Ok(())
+}
+
+fn validate_map(map: &contract::LevelState) -> Result<(), String> {
+    let cells = &map.cells;
+    let objects = &map.objects;
+    let width = cells.first().map_or(0, Vec::len);
+    if width == 0 {
+        return Err("level is empty".to_owned());
+    }
+    if cells.len() > MAX_SIZE || width > MAX_SIZE {
+        return Err(format!("level is larger than {0}x{0}", MAX_SIZE));
+    }
+    if cells.iter().any(|row| row.len() != width) {
+        return Err("level rows have different lengths".to_owned());
+    }
+    if objects.len() > MAX_OBJECTS {
+        return Err(format!("level has more than {} objects", MAX_OBJECTS));
+    }
+    let mut ids = HashSet::new();
+    for object in objects {
+        if !ids.insert(object.id) {
+            return Err(format!("duplicate object id {}", object.id));
+        }
+        let cell = cells
+            .get(object.row as usize)
+            .and_then(|row| row.get(object.col as usize));
+        match cell {
+            Some(contract::Cell::Empty) => {}
+            Some(contract::Cell::Wall) => return Err(format!("object {} is in a wall", object.id)),
+            None => return Err(format!("object {} is outside of the level", object.id)),
+        }
+    }
+    if !objects.iter().any(|o| o.kind == contract::ObjectKind::Pacman) {
+        return Err("level has no pacman".to_owned());
+    }
+    Ok(())
+}
+
+/// Evaluates a program against a user supplied level without storing
+/// anything. At most `max_steps` steps are simulated in total, even if the
+/// level allows more, split evenly between the public map and hidden
+/// variants.
+pub fn evaluate(
+    level: &contract::Level,
+    program: &contract::Program,
+    max_steps: u64,
+) -> Result<contract::SubmissionDetails, String> {
+    validate_level(level)?;
+    if program.rules.len() > MAX_RULES {
+        return Err(format!("program has more than {} rules", MAX_RULES));
+    }
+    let maps = level.hidden_variants.len() as u64 + 1;
+    let max_steps = cmp::max(1, max_steps / maps);
+    let max_steps = level.max_steps.map_or(max_steps, |steps| cmp::min(steps, max_steps));
+    Ok(evaluator::evaluate_all_variants(level, program, max_steps))
+}
+
+impl PacmanGame {
+    /// Counts a sandbox evaluation against the user's sandbox rate limit,
+    /// which is separate from the one for scored submissions. Returns
+    /// whether the evaluation is allowed.
+    pub fn sandbox_rate_limit(&mut self, user: &str, now: DateTime<Utc>) -> bool {
+        let limit = self.config.sandbox_rate_limit;
+        self.sandbox_limiters
+            .entry(user.to_owned())
+            .or_insert_with(|| RateLimiter::new(limit.count, limit.window))
+            .submit(now)
+            .is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use contract::{Cell, DeathState, LevelState, Move, Object, ObjectKind, Outcome, Program};
+
+    fn pacman(row: u64, col: u64) -> Object {
+        Object {
+            id: 0,
+            row,
+            col,
+            current_move: Move::Wait,
+            intended_move: Move::Wait,
+            state: DeathState::Alive,
+            kind: ObjectKind::Pacman,
+        }
+    }
+
+    fn level(cells: Vec<Vec<Cell>>, objects: Vec<Object>) -> contract::Level {
+        contract::Level {
+            state: LevelState { cells, objects },
+            ghost_program: Program { rules: Vec::new() },
+            max_steps: Some(1000),
+            max_rules: None,
+            hidden_variants: Vec::new(),
+            ranking: None,
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_levels() {
+        let row = vec![Cell::Wall, Cell::Empty, Cell::Wall];
+        assert!(validate_level(&level(Vec::new(), Vec::new())).is_err());
+        assert!(validate_level(&level(vec![row.clone(), vec![Cell::Wall]], vec![pacman(0, 1)])).is_err());
+        assert!(validate_level(&level(vec![row.clone()], vec![pacman(0, 0)])).is_err());
+        assert!(validate_level(&level(vec![row.clone()], vec![pacman(1, 1)])).is_err());
+        assert!(validate_level(&level(vec![row.clone()], Vec::new())).is_err());
+        assert!(validate_level(&level(vec![row.clone()], vec![pacman(0, 1), pacman(0, 1)])).is_err());
+        let unweighted = contract::Level {
+            ranking: Some(contract::Ranking {
+                criterion: contract::RankingCriterion::Weighted,
+                weights: None,
+            }),
+            ..level(vec![row.clone()], vec![pacman(0, 1)])
+        };
+        assert!(validate_level(&unweighted).is_err());
+        assert!(validate_level(&level(vec![row], vec![pacman(0, 1)])).is_ok());
+    }
+
+    #[test]
+    fn caps_steps() {
+        let cells = vec![vec![Cell::Wall, Cell::Empty, Cell::Wall, Cell::Empty, Cell::Wall]];
+        let berry = Object {
+            id: 1,
+            kind: ObjectKind::Berry,
+            ..pacman(0, 3)
+        };
+        let level = level(cells, vec![pacman(0, 1), berry]);
+        let details = evaluate(&level, &Program { rules: Vec::new() }, 10).unwrap();
+        assert_eq!(details.outcome, Outcome::OutOfMoves);
+        assert!(details.steps.len() <= 11);
+    }
+
+    #[test]
+    fn splits_steps_between_variants() {
+        let cells = vec![vec![Cell::Wall, Cell::Empty, Cell::Wall, Cell::Empty, Cell::Wall]];
+        let berry = Object {
+            id: 1,
+            kind: ObjectKind::Berry,
+            ..pacman(0, 3)
+        };
+        let mut level = level(cells, vec![pacman(0, 1), berry]);
+        level.hidden_variants = vec![level.state.clone(); 4];
+        let details = evaluate(&level, &Program { rules: Vec::new() }, 10).unwrap();
+        assert!(details.steps.len() <= 3);
+        level.hidden_variants.push(level.state.clone());
+        assert!(evaluate(&level, &Program { rules: Vec::new() }, 10).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use crate::{contract, time_format, validate_ranking, PacmanGame};
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Schedule {
+    entries: Vec<Entry>,
+    /// Number of entries that were already opened (or skipped).
+    position: usize,
+    /// Whether the last opened entry was closed by the schedule (or nothing
+    /// was opened yet).
+    closed: bool,
+}
+
+/// How far a schedule got, kept with replaced levels so that undoing a
+/// level change also undoes the schedule opening it.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub(crate) struct Progress {
+    position: usize,
+    closed: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Entry {
+    level: contract::Level,
+    #[serde(with = "time_format")]
+    opens_at: DateTime<Utc>,
+    #[serde(with = "time_format")]
+    closes_at: DateTime<Utc>,
+}
+
+impl Schedule {
+    /// Entries that have already ended at `now` are skipped.
+    pub fn from_contract(schedule: &contract::Schedule, now: DateTime<Utc>) -> Result<Self, String> {
+        let mut entries: Vec<Entry> = Vec::with_capacity(schedule.levels.len());
+        for (index, scheduled) in schedule.levels.iter().enumerate() {
+            let opens_at = DateTime::parse_from_rfc3339(&scheduled.opens_at)
+                .map_err(|e| format!("level {}: bad opening time: {}", index, e))?
+                .with_timezone(&Utc);
+            validate_ranking(&scheduled.level).map_err(|e| format!("level {}: {}", index, e))?;
+            let closes_at = opens_at + Duration::seconds(scheduled.duration as i64);
+            if let Some(previous) = entries.last() {
+                if opens_at < previous.closes_at {
+                    return Err(format!("level {} opens before the previous one closes", index));
+                }
+            }
+            entries.push(Entry {
+                level: scheduled.level.clone(),
+                opens_at,
+                closes_at,
+            });
+        }
+        let position = entries.iter().filter(|e| e.closes_at <= now).count();
+        Ok(Schedule {
+            entries,
+            position,
+            closed: true,
+        })
+    }
+
+    pub(crate) fn progress(&self) -> Progress {
+        Progress {
+            position: self.position,
+            closed: self.closed,
+        }
+    }
+
+    /// Progress saved before the schedule was replaced with a shorter one is
+    /// ignored.
+    pub(crate) fn restore(&mut self, progress: Progress) {
+        if progress.position <= self.entries.len() {
+            self.position = progress.position;
+            self.closed = progress.closed;
+        }
+    }
+
+    fn info(&self, now: DateTime<Utc>) -> contract::ScheduleInfo {
+        let current = self.position
+            .checked_sub(1)
+            .filter(|_| !self.closed)
+            .map(|index| index as u64);
+        let next_change = match current {
+            Some(index) => self.entries.get(index as usize).map(|e| e.closes_at),
+            None => self.entries.get(self.position).map(|e| e.opens_at),
+        };
+        contract::ScheduleInfo {
+            levels: self.entries
+                .iter()
+                .map(|e| contract::ScheduleEntry {
+                    opens_at: e.opens_at.to_rfc3339(),
+                    closes_at: e.closes_at.to_rfc3339(),
+                })
+                .collect(),
+            current,
+            seconds_until_change: next_change.map(|t| std::cmp::max(0, (t - now).num_seconds())),
+        }
+    }
+}
+
+impl PacmanGame {
+    /// Replaces the schedule. Setting the same schedule again (e.g. on
+    /// restart) keeps track of levels that were already opened. If a
+    /// scheduled level is open, the latest entry of the new schedule that
+    /// opens before `now` is taken to be the open one, so that the level is
+    /// still closed by the schedule (right away if that entry has ended).
+    pub fn set_schedule(&mut self, schedule: Schedule, now: DateTime<Utc>) {
+        let same_times = self.schedule.entries.len() == schedule.entries.len()
+            && self.schedule.entries
+                .iter()
+                .zip(&schedule.entries)
+                .all(|(a, b)| a.opens_at == b.opens_at && a.closes_at == b.closes_at);
+        if same_times {
+            self.schedule.entries = schedule.entries;
+            return;
+        }
+        let was_open = self.schedule.position > 0 && !self.schedule.closed;
+        self.schedule = schedule;
+        if was_open {
+            let opened = self.schedule.entries.iter().filter(|e| e.opens_at <= now).count();
+            if opened > 0 {
+                self.schedule.position = opened;
+                self.schedule.closed = false;
+            }
+        }
+    }
+
+    /// Opens and closes scheduled levels that are due at `now`, and freezes
+    /// the scoreboard before closing if configured. Returns whether anything
+    /// changed.
+    pub fn advance_schedule(&mut self, now: DateTime<Utc>) -> bool {
+        let mut changed = false;
+        while let Some(entry) = self.schedule.entries.get(self.schedule.position) {
+            if entry.opens_at > now {
+                break;
+            }
+            let level = entry.level.clone();
+            let opens_at = entry.opens_at;
+            log::info!("opening scheduled level {}", self.schedule.position);
+            self.set_level(level, opens_at);
+            self.schedule.position += 1;
+            self.schedule.closed = false;
+            changed = true;
+        }
+        if let Some(index) = self.schedule.position.checked_sub(1) {
+            let closes_at = self.schedule.entries[index].closes_at;
+            if let Some(freeze) = self.config.scoreboard_freeze {
+                let freeze_at = closes_at - freeze;
+                if !self.schedule.closed && self.freeze.is_none() && freeze_at <= now {
+                    log::info!("freezing scoreboard of scheduled level {}", index);
+                    self.freeze_scoreboard(freeze_at);
+                    changed = true;
+                }
+            }
+            if !self.schedule.closed && closes_at <= now {
+                log::info!("closing scheduled level {}", index);
+                self.set_level_state(true);
+                self.schedule.closed = true;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    pub fn schedule_info(&self, now: DateTime<Utc>) -> contract::ScheduleInfo {
+        self.schedule.info(now)
+    }
+}
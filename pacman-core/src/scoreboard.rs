@@ -1,11 +1,18 @@
-use std::cmp::Reverse;
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
-use crate::contract;
+use crate::contract::{self, RankingCriterion};
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Scoreboard {
     user_scores: HashMap<String, UserScore>,
+    /// Number of levels added with `add_level_scores`.
+    #[serde(default)]
+    level_count: usize,
+    /// Index following the last level added, kept in score dumps so that a
+    /// game continued from one doesn't reuse level indexes.
+    #[serde(default)]
+    next_level: u64,
 }
 
 impl Scoreboard {
@@ -13,114 +20,217 @@ impl Scoreboard {
         Self::default()
     }
 
-    pub fn add_user_evaluation(&mut self, user: &str, time: i64, size: usize, speed: usize) {
-        if !self.user_scores.contains_key(user) {
-            self.user_scores.insert(user.to_owned(), UserScore {
-                solved_levels: 0,
-                time_penalty: 0,
-                size_penalty: 0,
-                speed_penalty: 0,
-            });
-        }
-        let score = self.user_scores.get_mut(user).unwrap();
+    /// Adds a successful submission. For each metric the best value is
+    /// kept along with the submission that achieved it first.
+    pub fn add_user_evaluation(&mut self, user: &str, submission: u64, time: i64, size: usize, speed: usize, ranking: Option<&contract::Ranking>) {
+        let penalty = ranking_penalty(ranking, time, size, speed);
+        let score = self.user_score(user);
         if score.solved_levels == 0 {
             score.solved_levels = 1;
+            score.attempts += 1;
+            score.time_penalty = time;
+            score.size_penalty = size;
+            score.speed_penalty = speed;
+            score.ranking_penalty = Some(penalty);
+            score.best = Some(contract::BestSubmissions {
+                time: submission,
+                size: submission,
+                steps: submission,
+                penalty: submission,
+            });
+            return;
+        }
+        let best_penalty = score.ranking_penalty();
+        let best = score.best.get_or_insert(contract::BestSubmissions {
+            time: submission,
+            size: submission,
+            steps: submission,
+            penalty: submission,
+        });
+        if time < score.time_penalty {
             score.time_penalty = time;
+            best.time = submission;
+        }
+        if size < score.size_penalty {
             score.size_penalty = size;
+            best.size = submission;
+        }
+        if speed < score.speed_penalty {
             score.speed_penalty = speed;
-        } else {
-            score.time_penalty = std::cmp::min(score.time_penalty, time);
-            score.size_penalty = std::cmp::min(score.size_penalty, size);
-            score.speed_penalty = std::cmp::min(score.speed_penalty, speed);
+            best.steps = submission;
+        }
+        if penalty < best_penalty {
+            score.ranking_penalty = Some(penalty);
+            best.penalty = submission;
         }
     }
 
-    pub fn add_level_scores(&mut self, level_scores: &Scoreboard) {
+    /// Counts a failed submission, should only be added before the user
+    /// solves the level.
+    pub fn add_wrong_attempt(&mut self, user: &str) {
+        self.user_score(user).attempts += 1;
+    }
+
+    pub fn remove_user(&mut self, user: &str) {
+        self.user_scores.remove(user);
+    }
+
+    fn user_score(&mut self, user: &str) -> &mut UserScore {
+        self.user_scores
+            .entry(user.to_owned())
+            .or_insert(UserScore {
+                solved_levels: 0,
+                time_penalty: 0,
+                size_penalty: 0,
+                speed_penalty: 0,
+                attempts: 0,
+                ranking_penalty: Some(0.0),
+                best: None,
+                levels: Vec::new(),
+            })
+    }
+
+    /// Adds totals of the level with the given index in game history, and
+    /// its results as a new column of the per-level breakdown. Levels
+    /// without any results get no column. Penalties of different ranking
+    /// criteria can't be compared, so instead of adding them up the ranking
+    /// penalty of totals is the sum of user's ranks on solved levels.
+    pub fn add_level_scores(&mut self, level: u64, level_scores: &Scoreboard) {
+        self.next_level = self.next_level.max(level + 1);
+        if level_scores.user_scores.is_empty() {
+            return;
+        }
+        let column = self.level_count;
+        self.level_count += 1;
+        let ranks = level_scores.ranked()
+            .into_iter()
+            .filter(|(_, score, _)| score.solved_levels > 0)
+            .map(|(user, _, rank)| (user.as_str(), rank))
+            .collect::<HashMap<_, _>>();
         for (user, score) in &level_scores.user_scores {
+            let rank = ranks.get(user.as_str()).cloned();
+            let result = contract::LevelScore {
+                level,
+                rank,
+                solved: score.solved_levels > 0,
+                time_penalty: score.time_penalty,
+                rule_count: score.size_penalty as u64,
+                step_count: score.speed_penalty as u64,
+                attempts: score.attempts,
+            };
             if let Some(total) = self.user_scores.get_mut(user) {
                 total.solved_levels += score.solved_levels;
                 total.time_penalty += score.time_penalty;
                 total.size_penalty += score.size_penalty;
                 total.speed_penalty += score.speed_penalty;
+                total.attempts += score.attempts;
+                total.ranking_penalty = Some(total.ranking_penalty() + rank.unwrap_or(0) as f64);
+                total.levels.resize(column, None);
+                total.levels.push(Some(result));
             } else {
-                self.user_scores.insert(user.to_owned(), score.clone());
+                let mut levels = vec![None; column];
+                levels.push(Some(result));
+                // submission ids only make sense within a single level
+                self.user_scores.insert(user.to_owned(), UserScore {
+                    best: None,
+                    ranking_penalty: Some(rank.unwrap_or(0) as f64),
+                    levels,
+                    ..score.clone()
+                });
             }
         }
     }
 
-    pub fn to_contract_with_time(&self, title: &str) -> contract::Scoreboard {
-        let mut entries = self.user_scores
-            .iter()
-            .map(|(user, score)| (
-                user.clone(),
-                score.solved_levels,
-                score.time_penalty,
-            ))
-            .collect::<Vec<_>>();
-        entries.sort_by(|(user1, score1, penalty1), (user2, score2, penalty2)|
-            (score1, Reverse(penalty1), user1).cmp(&(score2, Reverse(penalty2), user2)).reverse()
-        );
+    /// The tie breaker shows the penalty of the given criterion, or the
+    /// ranking penalty itself if it is not known (i.e. the sum of ranks for
+    /// totals), or `-` if nothing was solved.
+    pub fn to_contract(&self, title: &str, criterion: Option<RankingCriterion>) -> contract::Scoreboard {
+        let entries = self.ranked()
+            .into_iter()
+            .map(|(user, score, rank)| {
+                contract::ScoreboardEntry {
+                    rank,
+                    user: user.clone(),
+                    solved: score.solved_levels,
+                    penalty: score.ranking_penalty(),
+                    time_penalty: score.time_penalty,
+                    rule_count: score.size_penalty as u64,
+                    step_count: score.speed_penalty as u64,
+                    attempts: score.attempts,
+                    best_submissions: score.best.clone(),
+                    levels: (0..self.level_count)
+                        .map(|level| score.levels.get(level).cloned().unwrap_or(None))
+                        .collect(),
+                    tie_breaker: match criterion {
+                        _ if score.solved_levels == 0 => "-".to_owned(),
+                        Some(RankingCriterion::Time) => format_time_penalty(score.time_penalty),
+                        Some(RankingCriterion::Size) => score.size_penalty.to_string(),
+                        Some(RankingCriterion::Steps) => score.speed_penalty.to_string(),
+                        Some(RankingCriterion::Weighted) | None => format_penalty(score.ranking_penalty()),
+                    },
+                }
+            })
+            .collect();
         contract::Scoreboard {
             title: title.to_owned(),
-            entries: entries
-                .into_iter()
-                .map(|(user, solved, penalty)| contract::ScoreboardEntry {
-                    user,
-                    solved,
-                    tie_breaker: format_time_penalty(penalty),
-                })
-                .collect(),
+            entries,
         }
     }
 
-    pub fn to_contract_with_size(&self, title: &str) -> contract::Scoreboard {
-        let mut entries = self.user_scores
-            .iter()
-            .map(|(user, score)| (
-                user.clone(),
-                score.solved_levels,
-                score.size_penalty,
-            ))
-            .collect::<Vec<_>>();
-        entries.sort_by(|(user1, score1, penalty1), (user2, score2, penalty2)|
-            (score1, Reverse(penalty1), user1).cmp(&(score2, Reverse(penalty2), user2)).reverse()
-        );
-        contract::Scoreboard {
-            title: title.to_owned(),
-            entries: entries
-                .into_iter()
-                .map(|(user, solved, penalty)| contract::ScoreboardEntry {
-                    user,
-                    solved,
-                    tie_breaker: penalty.to_string(),
-                })
-                .collect(),
-        }
+    /// Index following the last level added, also for dumps written before
+    /// it was kept.
+    pub fn next_level(&self) -> u64 {
+        self.user_scores
+            .values()
+            .flat_map(|score| score.levels.iter().flatten())
+            .map(|result| result.level + 1)
+            .fold(self.next_level, u64::max)
     }
 
-    pub fn to_contract_with_speed(&self, title: &str) -> contract::Scoreboard {
+    /// Users ranked by solved levels and then by ranking penalty, users who
+    /// only have failed attempts come last. Tied users share a rank.
+    fn ranked(&self) -> Vec<(&String, &UserScore, u64)> {
         let mut entries = self.user_scores
             .iter()
-            .map(|(user, score)| (
-                user.clone(),
-                score.solved_levels,
-                score.speed_penalty,
-            ))
+            .filter(|(_, score)| score.solved_levels > 0 || score.attempts > 0)
             .collect::<Vec<_>>();
-        entries.sort_by(|(user1, score1, penalty1), (user2, score2, penalty2)|
-            (score1, Reverse(penalty1), user1).cmp(&(score2, Reverse(penalty2), user2)).reverse()
-        );
-        contract::Scoreboard {
-            title: title.to_owned(),
-            entries: entries
-                .into_iter()
-                .map(|(user, solved, penalty)| contract::ScoreboardEntry {
-                    user,
-                    solved,
-                    tie_breaker: penalty.to_string(),
-                })
-                .collect(),
-        }
+        entries.sort_by(|(user1, score1), (user2, score2)| {
+            score2.solved_levels.cmp(&score1.solved_levels)
+                .then(score1.ranking_penalty().partial_cmp(&score2.ranking_penalty()).unwrap_or(Ordering::Equal))
+                .then(user2.cmp(user1))
+        });
+        let mut rank = 0;
+        let mut previous = None;
+        entries
+            .into_iter()
+            .enumerate()
+            .map(|(index, (user, score))| {
+                let key = (score.solved_levels, score.ranking_penalty());
+                if previous != Some(key) {
+                    rank = index as u64 + 1;
+                    previous = Some(key);
+                }
+                (user, score, rank)
+            })
+            .collect()
+    }
+}
+
+/// Penalty of a single solution by the ranking of its level.
+fn ranking_penalty(ranking: Option<&contract::Ranking>, time: i64, size: usize, speed: usize) -> f64 {
+    let criterion = ranking.map_or(RankingCriterion::Steps, |r| r.criterion);
+    match criterion {
+        RankingCriterion::Time => time as f64,
+        RankingCriterion::Size => size as f64,
+        RankingCriterion::Steps => speed as f64,
+        RankingCriterion::Weighted => match ranking.and_then(|r| r.weights.as_ref()) {
+            Some(weights) => {
+                time as f64 * weights.time
+                    + size as f64 * weights.size
+                    + speed as f64 * weights.steps
+            }
+            None => 0.0,
+        },
     }
 }
 
@@ -130,6 +240,27 @@ pub struct UserScore {
     time_penalty: i64,
     size_penalty: usize,
     speed_penalty: usize,
+    /// Submissions until the first success on each level, including it.
+    #[serde(default)]
+    attempts: u64,
+    /// Penalty by the ranking criterion of the level, or sum of them for
+    /// totals. Missing from scores stored before ranking criteria existed,
+    /// which were ranked by steps.
+    #[serde(default)]
+    ranking_penalty: Option<f64>,
+    /// Only kept for level scoreboards.
+    #[serde(default)]
+    best: Option<contract::BestSubmissions>,
+    /// Result on each level added to totals, `None` for levels without
+    /// user's submissions. Missing trailing levels are not stored.
+    #[serde(default)]
+    levels: Vec<Option<contract::LevelScore>>,
+}
+
+impl UserScore {
+    fn ranking_penalty(&self) -> f64 {
+        self.ranking_penalty.unwrap_or(self.speed_penalty as f64)
+    }
 }
 
 fn format_time_penalty(time: i64) -> String {
@@ -141,6 +272,15 @@ fn format_time_penalty(time: i64) -> String {
     }
 }
 
+/// Whole numbers are shown without decimals.
+fn format_penalty(penalty: f64) -> String {
+    if penalty.fract() == 0.0 {
+        format!("{}", penalty)
+    } else {
+        format!("{:.2}", penalty)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -159,4 +299,112 @@ mod tests {
         check(3600, "60:00");
         check(5438, "90:38");
     }
+
+    #[test]
+    fn weighted_ranking() {
+        let ranking = contract::Ranking {
+            criterion: RankingCriterion::Weighted,
+            weights: Some(contract::RankingWeights {
+                time: 0.5,
+                size: 10.0,
+                steps: 0.0,
+            }),
+        };
+        let mut scores = Scoreboard::new();
+        scores.add_user_evaluation("fast", 0, 10, 8, 5, Some(&ranking));
+        scores.add_user_evaluation("short", 1, 60, 2, 50, Some(&ranking));
+        scores.add_user_evaluation("short", 2, 100, 1, 50, Some(&ranking));
+        let scoreboard = scores.to_contract("Results", Some(RankingCriterion::Weighted));
+        let ranked = scoreboard.entries
+            .iter()
+            .map(|e| (e.user.as_str(), e.tie_breaker.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(ranked, vec![("short", "50"), ("fast", "85")]);
+        assert_eq!(scoreboard.entries[0].rule_count, 1);
+        assert_eq!(scoreboard.entries[0].time_penalty, 60);
+        let best = scoreboard.entries[0].best_submissions.as_ref().unwrap();
+        assert_eq!((best.time, best.size, best.steps, best.penalty), (1, 2, 1, 1));
+        assert_eq!(format_penalty(12.345), "12.35");
+    }
+
+    #[test]
+    fn old_scores_are_ranked_by_steps() {
+        let raw = r#"{"user_scores": {
+            "a": {"solved_levels": 1, "time_penalty": 10, "size_penalty": 3, "speed_penalty": 9},
+            "b": {"solved_levels": 1, "time_penalty": 20, "size_penalty": 4, "speed_penalty": 7}
+        }}"#;
+        let totals: Scoreboard = serde_json::from_str(raw).unwrap();
+        let scoreboard = totals.to_contract("Total", None);
+        let ranked = scoreboard.entries
+            .iter()
+            .map(|e| (e.user.as_str(), e.penalty))
+            .collect::<Vec<_>>();
+        assert_eq!(ranked, vec![("b", 7.0), ("a", 9.0)]);
+    }
+
+    #[test]
+    fn totals_sum_level_ranks() {
+        let time = contract::Ranking {
+            criterion: RankingCriterion::Time,
+            weights: None,
+        };
+        let mut first = Scoreboard::new();
+        first.add_user_evaluation("a", 0, 3000, 5, 9, Some(&time));
+        first.add_user_evaluation("b", 1, 60, 5, 9, Some(&time));
+        first.add_user_evaluation("c", 2, 600, 5, 9, Some(&time));
+        let mut second = Scoreboard::new();
+        second.add_user_evaluation("a", 0, 10, 5, 3, None);
+        second.add_user_evaluation("b", 1, 10, 5, 7, None);
+        second.add_user_evaluation("c", 2, 10, 5, 5, None);
+        let mut totals = Scoreboard::new();
+        totals.add_level_scores(1, &first);
+        totals.add_level_scores(2, &second);
+        totals.add_level_scores(3, &second);
+        let scoreboard = totals.to_contract("Total", None);
+        let ranked = scoreboard.entries
+            .iter()
+            .map(|e| (e.user.as_str(), e.rank, e.tie_breaker.as_str()))
+            .collect::<Vec<_>>();
+        // seconds of the first level don't outweigh steps of the others
+        assert_eq!(ranked, vec![("a", 1, "5"), ("c", 2, "6"), ("b", 3, "7")]);
+        let a = &scoreboard.entries[0].levels;
+        assert_eq!((a[0].as_ref().unwrap().rank, a[1].as_ref().unwrap().rank), (Some(3), Some(1)));
+    }
+
+    #[test]
+    fn tied_users_share_rank() {
+        let mut scores = Scoreboard::new();
+        scores.add_user_evaluation("a", 0, 10, 3, 5, None);
+        scores.add_user_evaluation("b", 1, 20, 4, 7, None);
+        scores.add_user_evaluation("c", 2, 30, 2, 7, None);
+        scores.add_user_evaluation("d", 3, 40, 1, 9, None);
+        let scoreboard = scores.to_contract("Results", Some(RankingCriterion::Steps));
+        let ranks = scoreboard.entries.iter().map(|e| e.rank).collect::<Vec<_>>();
+        assert_eq!(ranks, vec![1, 2, 2, 4]);
+    }
+
+    #[test]
+    fn totals_keep_level_breakdown() {
+        let mut first = Scoreboard::new();
+        first.add_user_evaluation("a", 0, 10, 3, 5, None);
+        first.add_wrong_attempt("b");
+        let mut second = Scoreboard::new();
+        second.add_user_evaluation("b", 0, 20, 4, 7, None);
+        let mut totals = Scoreboard::new();
+        totals.add_level_scores(1, &first);
+        totals.add_level_scores(2, &Scoreboard::new());
+        totals.add_level_scores(3, &second);
+        let totals: Scoreboard = serde_json::from_str(&serde_json::to_string(&totals).unwrap()).unwrap();
+        let scoreboard = totals.to_contract("Total", None);
+        let levels = |user: &str| scoreboard.entries
+            .iter()
+            .find(|e| e.user == user)
+            .unwrap()
+            .levels
+            .iter()
+            .map(|l| l.as_ref().map(|l| (l.level, l.solved, l.time_penalty, l.rule_count, l.step_count)))
+            .collect::<Vec<_>>();
+        assert_eq!(levels("a"), vec![Some((1, true, 10, 3, 5)), None]);
+        assert_eq!(levels("b"), vec![Some((1, false, 0, 0, 0)), Some((3, true, 20, 4, 7))]);
+    }
 }
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use crate::{contract, freeze, time_format, PacmanGame, PendingEvaluation, PreviousLevel, UserSubmission};
+use crate::rate_limiter::RateLimiter;
+use crate::schedule::Schedule;
+use crate::scoreboard::Scoreboard;
+
+/// Everything needed to restore a game, except for the config and manual
+/// play sessions.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GameSnapshot {
+    global_scores: Scoreboard,
+    #[serde(default)]
+    team_scores: Scoreboard,
+    level_scores: Scoreboard,
+    current_level: contract::Level,
+    is_level_closed: bool,
+    #[serde(with = "time_format")]
+    level_start: DateTime<Utc>,
+    submissions: Vec<UserSubmission>,
+    limiters: HashMap<String, RateLimiter>,
+    previous_levels: Vec<PreviousLevel>,
+    schedule: Schedule,
+    #[serde(default)]
+    freeze: Option<freeze::Freeze>,
+    #[serde(default)]
+    level_offset: u64,
+}
+
+impl PacmanGame {
+    pub fn raw_snapshot(&self) -> String {
+        let snapshot = GameSnapshot {
+            global_scores: self.global_scores.clone(),
+            team_scores: self.team_scores.clone(),
+            level_scores: self.level_scores.clone(),
+            current_level: self.current_level.clone(),
+            is_level_closed: self.is_level_closed,
+            level_start: self.level_start,
+            submissions: self.submissions.clone(),
+            limiters: self.limiters.clone(),
+            previous_levels: self.previous_levels.clone(),
+            schedule: self.schedule.clone(),
+            freeze: self.freeze.clone(),
+            level_offset: self.level_offset,
+        };
+        match serde_json::to_string_pretty(&snapshot) {
+            Ok(s) => s,
+            Err(e) => {
+                log::error!("{}", e);
+                e.to_string()
+            }
+        }
+    }
+
+    /// Replaces the whole game state with the snapshot. Submissions that were
+    /// not evaluated when the snapshot was taken are returned to be queued
+    /// again.
+    pub fn import_snapshot(&mut self, raw: &str) -> Result<Vec<PendingEvaluation>, String> {
+        let snapshot: GameSnapshot = serde_json::from_str(raw).map_err(|e| e.to_string())?;
+        let generation = self.generation + 1;
+        *self = PacmanGame::new(self.config.clone());
+        self.generation = generation;
+        self.global_scores = snapshot.global_scores;
+        self.team_scores = snapshot.team_scores;
+        self.level_scores = snapshot.level_scores;
+        self.current_level = snapshot.current_level;
+        self.is_level_closed = snapshot.is_level_closed;
+        self.level_start = snapshot.level_start;
+        self.submissions = snapshot.submissions;
+        self.limiters = snapshot.limiters;
+        self.previous_levels = snapshot.previous_levels;
+        self.schedule = snapshot.schedule;
+        self.freeze = snapshot.freeze;
+        self.level_offset = snapshot.level_offset;
+        Ok(self.requeue_unfinished())
+    }
+}
@@ -0,0 +1,262 @@
+use std::fmt::Write;
+use std::path::Path;
+use crate::contract::{Cell, DeathState, LevelState, Object, ObjectKind, SubmissionDetails};
+
+const CELL_SIZE: usize = 32;
+const FRAME_SECONDS: f64 = 0.4;
+const FILMSTRIP_COLUMNS: usize = 8;
+const FILMSTRIP_GAP: usize = 8;
+
+/// Links to images used in rendered replays, same ones as the editor uses.
+#[derive(Debug, Clone)]
+pub struct Images {
+    pub wall: String,
+    pub empty: String,
+    pub pacman: String,
+    pub ghost: String,
+    pub berry: String,
+}
+
+impl Images {
+    /// Refers to images by url, e.g. `/images/pacman.png`.
+    pub fn from_base_url(base: &str) -> Self {
+        let url = |name: &str| format!("{}/{}.png", base.trim_end_matches('/'), name);
+        Images {
+            wall: url("wall"),
+            empty: url("empty"),
+            pacman: url("pacman"),
+            ghost: url("ghost"),
+            berry: url("berry"),
+        }
+    }
+
+    /// Embeds images from the given directory as data urls, so that the
+    /// rendered svg does not depend on the server.
+    pub fn embed_from_dir(dir: &Path) -> std::io::Result<Self> {
+        let load = |name: &str| -> std::io::Result<String> {
+            let bytes = std::fs::read(dir.join(format!("{}.png", name)))?;
+            Ok(format!("data:image/png;base64,{}", base64(&bytes)))
+        };
+        Ok(Images {
+            wall: load("wall")?,
+            empty: load("empty")?,
+            pacman: load("pacman")?,
+            ghost: load("ghost")?,
+            berry: load("berry")?,
+        })
+    }
+
+    fn all(&self) -> [(&'static str, &str); 5] {
+        [
+            ("wall", &self.wall),
+            ("empty", &self.empty),
+            ("pacman", &self.pacman),
+            ("ghost", &self.ghost),
+            ("berry", &self.berry),
+        ]
+    }
+}
+
+/// Renders the replay as a looping animation, one frame per step.
+pub fn render_animated(details: &SubmissionDetails, images: &Images) -> String {
+    let frames = frames(details);
+    let (width, height) = level_size(&details.initial_state);
+    let total = FRAME_SECONDS * frames.len() as f64;
+    let mut svg = header(width, height);
+    write_defs(&mut svg, &details.initial_state, images);
+    svg.push_str("<use href=\"#level\"/>\n");
+    for (index, objects) in frames.iter().enumerate() {
+        let start = index as f64 / frames.len() as f64;
+        let end = (index + 1) as f64 / frames.len() as f64;
+        let (values, key_times) = if index == 0 {
+            ("visible;hidden".to_owned(), format!("0;{:.4}", end))
+        } else {
+            ("hidden;visible;hidden".to_owned(), format!("0;{:.4};{:.4}", start, end))
+        };
+        let _ = writeln!(
+            svg,
+            "<g visibility=\"hidden\"><animate attributeName=\"visibility\" values=\"{}\" \
+             keyTimes=\"{}\" calcMode=\"discrete\" dur=\"{:.1}s\" repeatCount=\"indefinite\"/>",
+            values,
+            key_times,
+            total,
+        );
+        write_objects(&mut svg, objects, 0, 0);
+        svg.push_str("</g>\n");
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Renders all steps side by side as a static image.
+pub fn render_filmstrip(details: &SubmissionDetails, images: &Images) -> String {
+    let frames = frames(details);
+    let (frame_width, frame_height) = level_size(&details.initial_state);
+    let columns = std::cmp::min(frames.len(), FILMSTRIP_COLUMNS);
+    let rows = (frames.len() + columns - 1) / columns;
+    let width = columns * (frame_width + FILMSTRIP_GAP) - FILMSTRIP_GAP;
+    let height = rows * (frame_height + FILMSTRIP_GAP) - FILMSTRIP_GAP;
+    let mut svg = header(width, height);
+    write_defs(&mut svg, &details.initial_state, images);
+    for (index, objects) in frames.iter().enumerate() {
+        let x = (index % columns) * (frame_width + FILMSTRIP_GAP);
+        let y = (index / columns) * (frame_height + FILMSTRIP_GAP);
+        let _ = writeln!(svg, "<use href=\"#level\" x=\"{}\" y=\"{}\"/>", x, y);
+        write_objects(&mut svg, objects, x, y);
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn frames(details: &SubmissionDetails) -> Vec<&[Object]> {
+    let mut frames = vec![details.initial_state.objects.as_slice()];
+    frames.extend(details.steps.iter().map(|s| s.objects.as_slice()));
+    frames
+}
+
+fn level_size(level: &LevelState) -> (usize, usize) {
+    let columns = level.cells.iter().map(Vec::len).max().unwrap_or(0);
+    (columns * CELL_SIZE, level.cells.len() * CELL_SIZE)
+}
+
+fn header(width: usize, height: usize) -> String {
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" xmlns:xlink=\"http://www.w3.org/1999/xlink\" \
+         width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">\n",
+        width.max(1),
+        height.max(1),
+    )
+}
+
+/// Each image is defined once and referenced by id, so that embedded
+/// images are not repeated for every cell and object.
+fn write_defs(svg: &mut String, level: &LevelState, images: &Images) {
+    svg.push_str("<defs>\n");
+    for (id, href) in images.all().iter() {
+        let _ = writeln!(
+            svg,
+            "<image id=\"{0}\" href=\"{1}\" xlink:href=\"{1}\" width=\"{2}\" height=\"{2}\"/>",
+            id,
+            href,
+            CELL_SIZE,
+        );
+    }
+    svg.push_str("<g id=\"level\">\n");
+    for (row, cells) in level.cells.iter().enumerate() {
+        for (col, cell) in cells.iter().enumerate() {
+            let id = match cell {
+                Cell::Wall => "wall",
+                Cell::Empty => "empty",
+            };
+            write_use(svg, id, col * CELL_SIZE, row * CELL_SIZE, None);
+        }
+    }
+    svg.push_str("</g></defs>\n");
+}
+
+fn write_objects(svg: &mut String, objects: &[Object], x: usize, y: usize) {
+    let mut objects = objects.to_vec();
+    // pacman and ghosts are drawn over berries
+    objects.sort_by_key(|o| o.kind);
+    for obj in &objects {
+        let opacity = match obj.state {
+            DeathState::Alive => None,
+            DeathState::DiesAtEnd | DeathState::DiesInMiddle => Some(0.4),
+        };
+        let id = match obj.kind {
+            ObjectKind::Pacman => "pacman",
+            ObjectKind::Ghost => "ghost",
+            ObjectKind::Berry => "berry",
+        };
+        write_use(
+            svg,
+            id,
+            x + obj.col as usize * CELL_SIZE,
+            y + obj.row as usize * CELL_SIZE,
+            opacity,
+        );
+    }
+}
+
+fn write_use(svg: &mut String, id: &str, x: usize, y: usize, opacity: Option<f64>) {
+    let _ = write!(svg, "<use href=\"#{0}\" xlink:href=\"#{0}\" x=\"{1}\" y=\"{2}\"", id, x, y);
+    if let Some(opacity) = opacity {
+        let _ = write!(svg, " opacity=\"{}\"", opacity);
+    }
+    svg.push_str("/>\n");
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).cloned().unwrap_or(0),
+            chunk.get(2).cloned().unwrap_or(0),
+        ];
+        let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_tests() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foob"), "Zm9vYg==");
+        assert_eq!(base64(b"fooba"), "Zm9vYmE=");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn images_are_embedded_once() {
+        let images = Images {
+            wall: "data:wall".to_owned(),
+            empty: "data:empty".to_owned(),
+            pacman: "data:pacman".to_owned(),
+            ghost: "data:ghost".to_owned(),
+            berry: "data:berry".to_owned(),
+        };
+        let pacman = Object {
+            id: 0,
+            row: 0,
+            col: 1,
+            current_move: crate::contract::Move::Wait,
+            intended_move: crate::contract::Move::Wait,
+            state: DeathState::Alive,
+            kind: ObjectKind::Pacman,
+        };
+        let state = LevelState {
+            cells: vec![vec![Cell::Wall, Cell::Empty, Cell::Empty, Cell::Wall]],
+            objects: vec![pacman],
+        };
+        let step = crate::contract::Step {
+            objects: state.objects.clone(),
+        };
+        let details = SubmissionDetails {
+            initial_state: state,
+            steps: vec![step.clone(), step],
+            outcome: crate::contract::Outcome::OutOfMoves,
+            hidden_outcomes: Vec::new(),
+            program: None,
+        };
+        for svg in &[render_animated(&details, &images), render_filmstrip(&details, &images)] {
+            assert_eq!(svg.matches(" href=\"data:wall\"").count(), 1);
+            assert_eq!(svg.matches(" href=\"data:pacman\"").count(), 1);
+        }
+    }
+}
@@ -1,9 +1,12 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use pacman_core::contract;
+use serde::Deserialize;
 
 #[derive(Clone)]
 pub struct User {
     pub name: String,
     pub password: String,
+    pub team: Option<String>,
 }
 
 pub fn read_from_file(file: &Path) -> std::io::Result<Vec<User>> {
@@ -17,11 +20,13 @@ pub fn read_from_file(file: &Path) -> std::io::Result<Vec<User>> {
             let mut parts = line.split(',');
             let name = parts.next().map(str::trim);
             let password = parts.next().map(str::trim);
+            let team = parts.next().map(str::trim).filter(|team| *team != "");
             let trailing = parts.next();
             match (name, password, trailing) {
                 (Some(name), Some(password), None) => Some(User {
                     name: name.to_owned(),
                     password: password.to_owned(),
+                    team: team.map(str::to_owned),
                 }),
                 _ => {
                     log::warn!("bad config line {}, skipping", index + 1);
@@ -31,3 +36,98 @@ pub fn read_from_file(file: &Path) -> std::io::Result<Vec<User>> {
         })
         .collect())
 }
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ScheduleFile {
+    levels: Vec<ScheduledLevelFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ScheduledLevelFile {
+    /// Level file (same format as posted to `/api/admin/level`), relative
+    /// to the schedule file.
+    file: String,
+    opens_at: String,
+    duration: u64,
+}
+
+pub fn read_schedule(file: &Path) -> Result<contract::Schedule, String> {
+    let text = std::fs::read_to_string(file).map_err(|e| e.to_string())?;
+    let schedule: ScheduleFile = serde_json::from_str(&text).map_err(|e| e.to_string())?;
+    let dir = file.parent().unwrap_or_else(|| Path::new("."));
+    let mut levels = Vec::with_capacity(schedule.levels.len());
+    for scheduled in schedule.levels {
+        let path = dir.join(&scheduled.file);
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let level: contract::SetLevel = serde_json::from_str(&text)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        levels.push(contract::ScheduledLevel {
+            level: level.level,
+            opens_at: scheduled.opens_at,
+            duration: scheduled.duration,
+        });
+    }
+    Ok(contract::Schedule { levels })
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RoomsFile {
+    rooms: Vec<RoomFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RoomFile {
+    name: String,
+    /// Paths are relative to the rooms file.
+    users: String,
+    admin_token: String,
+    score_dir: Option<String>,
+    journal: Option<String>,
+    schedule: Option<String>,
+}
+
+/// Everything needed to set up a single contest room.
+pub struct RoomConfig {
+    pub name: String,
+    pub users: Vec<User>,
+    pub admin_token: String,
+    pub scores: Option<PathBuf>,
+    pub snapshot: Option<PathBuf>,
+    pub score_dir: Option<PathBuf>,
+    pub journal: Option<PathBuf>,
+    pub schedule: Option<PathBuf>,
+}
+
+pub fn read_rooms(file: &Path) -> Result<Vec<RoomConfig>, String> {
+    let text = std::fs::read_to_string(file).map_err(|e| e.to_string())?;
+    let rooms: RoomsFile = serde_json::from_str(&text).map_err(|e| e.to_string())?;
+    let dir = file.parent().unwrap_or_else(|| Path::new("."));
+    let mut configs = Vec::with_capacity(rooms.rooms.len());
+    for room in rooms.rooms {
+        let valid_name = !room.name.is_empty() && room.name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid_name {
+            return Err(format!("bad room name: {:?}", room.name));
+        }
+        let users_file = dir.join(&room.users);
+        let users = read_from_file(&users_file)
+            .map_err(|e| format!("{}: {}", users_file.display(), e))?;
+        configs.push(RoomConfig {
+            name: room.name,
+            users,
+            admin_token: room.admin_token,
+            scores: None,
+            snapshot: None,
+            score_dir: room.score_dir.map(|path| dir.join(path)),
+            journal: room.journal.map(|path| dir.join(path)),
+            schedule: room.schedule.map(|path| dir.join(path)),
+        });
+    }
+    Ok(configs)
+}
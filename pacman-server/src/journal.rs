@@ -0,0 +1,36 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use pacman_core::journal::{self, Event};
+
+/// Append-only file with every state changing event, one per line.
+pub struct Journal {
+    file: File,
+}
+
+impl Journal {
+    /// Opens (or creates) the journal for appending, returning events that
+    /// were already recorded in it.
+    pub fn open(path: &Path) -> io::Result<(Journal, Vec<Event>)> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let events = journal::read_events(&text)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if !text.is_empty() && !text.ends_with('\n') {
+            // finish the line left by a crash so that new entries are readable
+            file.write_all(b"\n")?;
+        }
+        Ok((Journal { file }, events))
+    }
+
+    pub fn append(&mut self, event: &Event) -> io::Result<()> {
+        let mut line = event.to_line();
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.file.sync_data()
+    }
+}
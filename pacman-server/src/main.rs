@@ -1,31 +1,147 @@
 mod config;
+mod journal;
+mod snapshots;
+mod worker;
 
+use std::collections::HashMap;
+use std::ops::Deref;
 use std::path::{Path as StdPath, PathBuf};
 use std::sync::{Arc, Mutex};
-use actix_web::{App, HttpResponse, HttpRequest, Json, Path, Result, State, fs::{self, NamedFile}};
+use std::sync::mpsc::{self, Sender};
+use actix_web::{App, FromRequest, FutureResponse, HttpResponse, HttpRequest, Json, Path, Query, Result, State, fs::{self, NamedFile}};
 use actix_web::http::Cookie;
 use chrono::Duration;
+use futures::{future, Future};
+use futures::sync::oneshot;
+use serde::Deserialize;
 use time::Duration as Dur;
-use pacman_core::{contract, GameConfig, PacmanGame, RateLimit};
+use pacman_core::{contract, replay, sandbox, svg, FinishedEvaluation, GameConfig, PacmanGame, PendingEvaluation, RateLimit, Rejudge, Visibility, validate_ranking};
+use pacman_core::journal::Event;
+use pacman_core::schedule::Schedule;
 use structopt::StructOpt;
-use crate::config::User;
+use crate::config::{RoomConfig, User};
+use crate::journal::Journal;
+
+/// Room used by routes that are not under `/api/rooms/{room}`.
+const DEFAULT_ROOM: &str = "default";
 
 #[derive(Clone)]
 struct AppState {
+    rooms: Arc<HashMap<String, Arc<Room>>>,
+    config: GameConfig,
+    replay_images: Arc<svg::Images>,
+}
+
+/// A single contest with its own game, users and admin token.
+struct Room {
+    name: String,
     game: Arc<Mutex<PacmanGame>>,
-    users: Arc<[User]>,
-    admin_token: Arc<str>,
-    score_dir: Option<Arc<StdPath>>,
+    /// Unbounded, live submissions are limited by `--max-queued` and
+    /// rejudges by the number of submissions on the level.
+    evaluations: Mutex<Sender<PendingEvaluation>>,
+    /// Rejudge requests waiting for their evaluations.
+    rejudges: Mutex<Vec<(Rejudge, oneshot::Sender<Option<contract::RejudgeResult>>)>>,
+    users: Vec<User>,
+    admin_token: String,
+    score_dir: Option<PathBuf>,
+    journal: Option<Mutex<Journal>>,
+    /// Game config of this room (with its teams), restored on reset.
     config: GameConfig,
 }
 
-impl AppState {
+impl Room {
     fn is_password_correct(&self, user: &str, password: &str) -> bool {
         self.users.iter().any(|u| u.name == user && u.password == password)
     }
+
+    /// User logged in with cookies, if the password is correct.
+    fn cookie_user(&self, request: &HttpRequest<AppState>) -> Option<String> {
+        let user = request.cookie("user")?;
+        let password = request.cookie("password")?;
+        if self.is_password_correct(user.value(), password.value()) {
+            Some(user.value().to_owned())
+        } else {
+            None
+        }
+    }
+
+    /// Appends the event to the journal. Should be called while holding the
+    /// game lock, so that events are recorded in the order they were applied.
+    fn record(&self, event: Event) {
+        if let Some(journal) = &self.journal {
+            if let Err(e) = journal.lock().unwrap().append(&event) {
+                log::error!("room {}: failed to write to journal: {}", self.name, e);
+            }
+        }
+    }
+
+    /// Sends the evaluation to the workers. Should be called outside of the
+    /// game lock, workers need it to make progress.
+    fn queue_evaluation(&self, pending: PendingEvaluation) {
+        let id = pending.id;
+        if self.evaluations.lock().unwrap().send(pending).is_err() {
+            log::error!("evaluation workers are gone, submission {} is stuck", id);
+        }
+    }
+
+    /// Applies an evaluation that was run outside of the game lock. Applied
+    /// evaluations are journaled, so that replays drop the same ones.
+    fn finish_evaluation(&self, evaluation: FinishedEvaluation) {
+        let mut game = self.game.lock().unwrap();
+        let event = evaluation.event();
+        if game.finish_evaluation(evaluation) {
+            self.record(event);
+        }
+        self.answer_rejudges(&game);
+    }
+
+    /// Answers rejudge requests that are finished or were interrupted.
+    /// Should be called while holding the game lock.
+    fn answer_rejudges(&self, game: &PacmanGame) {
+        let mut rejudges = self.rejudges.lock().unwrap();
+        let (finished, running): (Vec<_>, Vec<_>) = rejudges
+            .drain(..)
+            .partition(|(job, _)| game.is_rejudge_finished(job));
+        *rejudges = running;
+        for (job, sender) in finished {
+            let _ = sender.send(game.rejudge_result(&job));
+        }
+    }
 }
 
-fn submit(state: State<AppState>, submit: Json<contract::Submit>, request: HttpRequest<AppState>) -> Json<contract::SubmitResponse> {
+/// Room addressed by the request, either by the `{room}` path segment or
+/// the default one.
+struct CurrentRoom(Arc<Room>);
+
+impl Deref for CurrentRoom {
+    type Target = Room;
+
+    fn deref(&self) -> &Room {
+        &self.0
+    }
+}
+
+impl FromRequest<AppState> for CurrentRoom {
+    type Config = ();
+    type Result = Result<CurrentRoom>;
+
+    fn from_request(request: &HttpRequest<AppState>, _: &()) -> Self::Result {
+        let name = request.match_info().get("room").unwrap_or(DEFAULT_ROOM);
+        match request.state().rooms.get(name) {
+            Some(room) => Ok(CurrentRoom(room.clone())),
+            None => Err(actix_web::error::ErrorNotFound("no such room")),
+        }
+    }
+}
+
+/// Path of submission routes. Not a plain `Path<u64>`, because routes under
+/// `/api/rooms/{room}` have the room as another parameter.
+#[derive(Deserialize)]
+struct SubmissionPath {
+    id: u64,
+}
+
+fn submit(room: CurrentRoom, submit: Json<contract::Submit>, request: HttpRequest<AppState>) -> Json<contract::SubmitResult> {
     let submit = submit.into_inner();
     let user_cookie = request.cookie("user");
     let password_cookie = request.cookie("password");
@@ -38,47 +154,291 @@ fn submit(state: State<AppState>, submit: Json<contract::Submit>, request: HttpR
         .or(password_cookie.as_ref().map(|c| c.value()))
         .unwrap_or("<missing>");
     log::info!("POST /submit by {} (password {})", user, password);
-    if !state.is_password_correct(user, password) {
+    if !room.is_password_correct(user, password) {
         log::warn!("POST /submit by {} - unauthorized", user);
-        return Json(contract::SubmitResponse::Unauthorized);
+        return Json(contract::SubmitResult {
+            response: contract::SubmitResponse::Unauthorized,
+            submission_id: None,
+            level: None,
+        });
     }
-    let mut game = state.game.lock().unwrap();
     let now = chrono::Utc::now();
-    let result = game.submit_program(user, &submit.program, now);
-    Json(result)
+    let practice = submit.practice.unwrap_or(false) || submit.level.is_some();
+    let queued = {
+        let mut game = room.game.lock().unwrap();
+        if practice {
+            let queued = game.queue_practice(user, &submit.program, submit.level, now);
+            if queued.is_ok() {
+                room.record(Event::Practice {
+                    user: user.to_owned(),
+                    program: submit.program.clone(),
+                    level: submit.level,
+                    time: now,
+                });
+            }
+            queued
+        } else {
+            let queued = game.queue_program(user, &submit.program, now);
+            if queued.is_ok() {
+                room.record(Event::Submit {
+                    user: user.to_owned(),
+                    program: submit.program.clone(),
+                    time: now,
+                });
+            }
+            queued
+        }
+    };
+    match queued {
+        Ok(pending) => {
+            let (id, level) = (pending.id, pending.level_index);
+            room.queue_evaluation(pending);
+            Json(contract::SubmitResult {
+                response: contract::SubmitResponse::Ok,
+                submission_id: Some(id),
+                level: Some(level),
+            })
+        }
+        Err(response) => Json(contract::SubmitResult {
+            response,
+            submission_id: None,
+            level: None,
+        }),
+    }
 }
 
-fn get_submissions(state: State<AppState>) -> Json<contract::Submissions> {
-    let game = state.game.lock().unwrap();
-    let submissions = game.all_submissions();
+fn evaluate_sandbox(state: State<AppState>, room: CurrentRoom, sandbox: Json<contract::Sandbox>, request: HttpRequest<AppState>) -> Json<contract::SandboxResult> {
+    let sandbox = sandbox.into_inner();
+    let rejected = |response| Json(contract::SandboxResult {
+        response,
+        error: None,
+        details: None,
+    });
+    let user = match (&sandbox.user, &sandbox.password) {
+        (Some(user), Some(password)) if room.is_password_correct(user, password) => Some(user.clone()),
+        (Some(_), Some(_)) => None,
+        _ => room.cookie_user(&request),
+    };
+    let user = match user {
+        Some(user) => user,
+        None => return rejected(contract::SandboxResponse::Unauthorized),
+    };
+    if !room.game.lock().unwrap().sandbox_rate_limit(&user, chrono::Utc::now()) {
+        return rejected(contract::SandboxResponse::RateLimitExceeded);
+    }
+    // evaluated outside of the game lock, nothing is stored
+    match sandbox::evaluate(&sandbox.level, &sandbox.program, state.config.sandbox_max_steps) {
+        Ok(details) => Json(contract::SandboxResult {
+            response: contract::SandboxResponse::Ok,
+            error: None,
+            details: Some(details),
+        }),
+        Err(e) => Json(contract::SandboxResult {
+            response: contract::SandboxResponse::InvalidLevel,
+            error: Some(e),
+            details: None,
+        }),
+    }
+}
+
+fn get_submissions(room: CurrentRoom, query: Query<contract::SubmissionsQuery>, request: HttpRequest<AppState>) -> Json<contract::Submissions> {
+    let viewer = room.cookie_user(&request);
+    let game = room.game.lock().unwrap();
+    let submissions = game.query_submissions(&query, viewer.as_ref().map(String::as_str));
     Json(submissions)
 }
 
-fn get_submission(state: State<AppState>, id: Path<u64>) -> HttpResponse {
-    let game = state.game.lock().unwrap();
-    let details = game.submission_details(id.into_inner());
-    match details {
-        Some(details) => HttpResponse::Ok().json(details),
+fn my_submissions(room: CurrentRoom, query: Query<contract::SubmissionsQuery>, request: HttpRequest<AppState>) -> HttpResponse {
+    let user = match room.cookie_user(&request) {
+        Some(user) => user,
+        None => return HttpResponse::Unauthorized().finish(),
+    };
+    let history = room.game.lock().unwrap().user_submissions(&user, &query);
+    HttpResponse::Ok().json(history)
+}
+
+fn get_submission(room: CurrentRoom, path: Path<SubmissionPath>, query: Query<contract::ReplayQuery>, request: HttpRequest<AppState>) -> HttpResponse {
+    let viewer = room.cookie_user(&request);
+    let game = room.game.lock().unwrap();
+    let details = game.submission_details(query.level, path.id, viewer.as_ref().map(String::as_str));
+    match (details, query.format) {
+        (Some(details), Some(contract::ReplayFormat::Compact)) => {
+            HttpResponse::Ok().json(replay::compress(&details))
+        }
+        (Some(details), _) => HttpResponse::Ok().json(details),
+        (None, _) => HttpResponse::NotFound().finish(),
+    }
+}
+
+fn get_submission_svg(state: State<AppState>, room: CurrentRoom, path: Path<SubmissionPath>, query: Query<contract::SvgQuery>, request: HttpRequest<AppState>) -> HttpResponse {
+    let viewer = room.cookie_user(&request);
+    let details = room.game.lock().unwrap().submission_details(query.level, path.id, viewer.as_ref().map(String::as_str));
+    let details = match details {
+        Some(details) => details,
+        None => return HttpResponse::NotFound().finish(),
+    };
+    let svg = match query.mode {
+        Some(contract::SvgMode::Filmstrip) => svg::render_filmstrip(&details, &state.replay_images),
+        Some(contract::SvgMode::Animated) | None => svg::render_animated(&details, &state.replay_images),
+    };
+    HttpResponse::Ok()
+        .content_type("image/svg+xml")
+        .body(svg)
+}
+
+fn get_submission_status(room: CurrentRoom, path: Path<SubmissionPath>, query: Query<contract::StatusQuery>, request: HttpRequest<AppState>) -> HttpResponse {
+    let viewer = room.cookie_user(&request);
+    let game = room.game.lock().unwrap();
+    match game.submission_status(query.level, path.id, viewer.as_ref().map(String::as_str)) {
+        Some(status) => HttpResponse::Ok().json(status),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+fn start_play(room: CurrentRoom, request: HttpRequest<AppState>) -> HttpResponse {
+    let user = match room.cookie_user(&request) {
+        Some(user) => user,
+        None => return HttpResponse::Unauthorized().finish(),
+    };
+    let level = room.game.lock().unwrap().start_play(&user);
+    HttpResponse::Ok().json(level)
+}
+
+fn play_move(room: CurrentRoom, play: Json<contract::PlayMove>, request: HttpRequest<AppState>) -> HttpResponse {
+    let user = match room.cookie_user(&request) {
+        Some(user) => user,
+        None => return HttpResponse::Unauthorized().finish(),
+    };
+    let step = room.game.lock().unwrap().play_move(&user, play.next_move);
+    match step {
+        Some(step) => HttpResponse::Ok().json(step),
         None => HttpResponse::NotFound().finish(),
     }
 }
 
-fn scoreboard(state: State<AppState>) -> Json<contract::Scoreboards> {
-    let game = state.game.lock().unwrap();
-    let scoreboards = game.get_scores();
+fn scoreboard(room: CurrentRoom, query: Query<contract::ScoreboardQuery>, request: HttpRequest<AppState>) -> Json<contract::Scoreboards> {
+    let viewer = room.cookie_user(&request);
+    let game = room.game.lock().unwrap();
+    let scoreboards = if query.admin_token.as_ref() == Some(&room.admin_token) {
+        game.get_scores()
+    } else {
+        game.get_scores_for(viewer.as_ref().map(String::as_str))
+    };
     Json(scoreboards)
 }
 
-fn set_level(state: State<AppState>, set: Json<contract::SetLevel>) -> HttpResponse {
+fn freeze(room: CurrentRoom, freeze: Json<contract::Freeze>) -> HttpResponse {
+    if freeze.admin_token != room.admin_token {
+        log::debug!("invalid admin token: {:?}", freeze.admin_token);
+        return HttpResponse::Unauthorized().finish();
+    }
+    let mut game = room.game.lock().unwrap();
+    let now = chrono::Utc::now();
+    room.record(Event::Freeze { time: now });
+    game.freeze_scoreboard(now);
+    log::info!("scoreboard frozen");
+    HttpResponse::Ok().finish()
+}
+
+fn unfreeze(room: CurrentRoom, unfreeze: Json<contract::Unfreeze>) -> HttpResponse {
+    if unfreeze.admin_token != room.admin_token {
+        log::debug!("invalid admin token: {:?}", unfreeze.admin_token);
+        return HttpResponse::Unauthorized().finish();
+    }
+    let count = unfreeze.count.unwrap_or(1);
+    let mut game = room.game.lock().unwrap();
+    room.record(Event::Unfreeze { count });
+    let remaining = game.unfreeze(count);
+    log::info!("revealed {} frozen submissions, {} still hidden", count, remaining);
+    HttpResponse::Ok().json(contract::UnfreezeResult { remaining })
+}
+
+fn set_level(room: CurrentRoom, set: Json<contract::SetLevel>) -> HttpResponse {
     let set = set.into_inner();
-    if set.admin_token != state.admin_token.as_ref() {
+    if set.admin_token != room.admin_token {
         log::debug!("invalid admin token: {:?}", set.admin_token);
         return HttpResponse::Unauthorized().finish();
     }
-    let mut game = state.game.lock().unwrap();
+    if let Err(e) = validate_ranking(&set.level) {
+        return HttpResponse::BadRequest().body(e);
+    }
+    let mut game = room.game.lock().unwrap();
     let now = chrono::Utc::now();
+    room.record(Event::SetLevel {
+        level: set.level.clone(),
+        time: now,
+    });
     game.set_level(set.level, now);
-    if let Some(dir) = state.score_dir.as_ref() {
+    dump_scores(&room, &game);
+    HttpResponse::Ok().finish()
+}
+
+fn set_schedule(room: CurrentRoom, set: Json<contract::SetSchedule>) -> HttpResponse {
+    let set = set.into_inner();
+    if set.admin_token != room.admin_token {
+        log::debug!("invalid admin token: {:?}", set.admin_token);
+        return HttpResponse::Unauthorized().finish();
+    }
+    let now = chrono::Utc::now();
+    let schedule = match Schedule::from_contract(&set.schedule, now) {
+        Ok(schedule) => schedule,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let mut game = room.game.lock().unwrap();
+    game.set_schedule(schedule, now);
+    room.record(Event::SetSchedule {
+        schedule: set.schedule,
+        time: now,
+    });
+    HttpResponse::Ok().finish()
+}
+
+fn get_schedule(room: CurrentRoom) -> Json<contract::ScheduleInfo> {
+    let game = room.game.lock().unwrap();
+    Json(game.schedule_info(chrono::Utc::now()))
+}
+
+/// Opens and closes scheduled levels in all rooms when they are due.
+fn run_scheduler(rooms: Arc<HashMap<String, Arc<Room>>>) {
+    loop {
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        for room in rooms.values() {
+            let mut game = room.game.lock().unwrap();
+            let now = chrono::Utc::now();
+            if game.advance_schedule(now) {
+                room.record(Event::AdvanceSchedule { time: now });
+                dump_scores(room, &game);
+            }
+        }
+    }
+}
+
+fn undo_level_change(room: CurrentRoom, undo: Json<contract::UndoLevelChange>) -> HttpResponse {
+    let undo = undo.into_inner();
+    if undo.admin_token != room.admin_token {
+        log::debug!("invalid admin token: {:?}", undo.admin_token);
+        return HttpResponse::Unauthorized().finish();
+    }
+    let steps = undo.steps.unwrap_or(1) as usize;
+    let pending = {
+        let mut game = room.game.lock().unwrap();
+        match game.undo_level_change(steps) {
+            Some(pending) => {
+                room.record(Event::UndoLevelChange { steps });
+                dump_scores(&room, &game);
+                pending
+            }
+            None => return HttpResponse::Conflict().finish(),
+        }
+    };
+    for pending in pending {
+        room.queue_evaluation(pending);
+    }
+    HttpResponse::Ok().finish()
+}
+
+fn dump_scores(room: &Room, game: &PacmanGame) {
+    if let Some(dir) = room.score_dir.as_ref() {
         let dump = game.raw_scoreboard();
         let timestamp = time::at(time::get_time()).rfc3339().to_string();
         let timestamp = timestamp.replace(':', "-");
@@ -91,55 +451,146 @@ fn set_level(state: State<AppState>, set: Json<contract::SetLevel>) -> HttpRespo
             Err(e) => log::error!("failed to write dump to {}: {}", file.display(), e),
         }
     }
-    HttpResponse::Ok().finish()
 }
 
-fn set_level_state(state: State<AppState>, set: Json<contract::SetLevelState>) -> HttpResponse {
+fn set_level_state(room: CurrentRoom, set: Json<contract::SetLevelState>) -> HttpResponse {
     let set = set.into_inner();
-    if set.admin_token != state.admin_token.as_ref() {
+    if set.admin_token != room.admin_token {
         log::debug!("invalid admin token: {:?}", set.admin_token);
         return HttpResponse::Unauthorized().finish();
     }
-    let mut game = state.game.lock().unwrap();
+    let mut game = room.game.lock().unwrap();
     game.set_level_state(set.is_closed);
+    room.record(Event::SetLevelState {
+        closed: set.is_closed,
+    });
     HttpResponse::Ok().finish()
 }
 
-fn reset(state: State<AppState>, reset: Json<contract::Reset>) -> HttpResponse {
+fn reset(room: CurrentRoom, reset: Json<contract::Reset>) -> HttpResponse {
     let reset = reset.into_inner();
-    if reset.admin_token != state.admin_token.as_ref() {
+    if reset.admin_token != room.admin_token {
         log::debug!("invalid admin token: {:?}", reset.admin_token);
         return HttpResponse::Unauthorized().finish();
     }
-    let mut game = match state.game.lock() {
+    let mut game = match room.game.lock() {
         Ok(game) => game,
         Err(poisoned) => poisoned.into_inner(),
     };
-    *game = PacmanGame::new(state.config.clone());
+    game.set_config(room.config.clone());
+    game.reset();
+    room.record(Event::Reset);
     HttpResponse::Ok().finish()
 }
 
-fn rate_limit(state: State<AppState>, limit: Json<contract::RateLimit>) -> HttpResponse {
+/// Answers once the workers have evaluated every submission again.
+fn rejudge(room: CurrentRoom, rejudge: Json<contract::Rejudge>) -> FutureResponse<HttpResponse> {
+    let rejudge = rejudge.into_inner();
+    if rejudge.admin_token != room.admin_token {
+        log::debug!("invalid admin token: {:?}", rejudge.admin_token);
+        return Box::new(future::ok(HttpResponse::Unauthorized().finish()));
+    }
+    if let Some(Err(e)) = rejudge.level.as_ref().map(validate_ranking) {
+        return Box::new(future::ok(HttpResponse::BadRequest().body(e)));
+    }
+    let (sender, receiver) = oneshot::channel();
+    let pending = {
+        let mut game = room.game.lock().unwrap();
+        room.record(Event::Rejudge {
+            level: rejudge.level.clone(),
+        });
+        let (job, pending) = game.start_rejudge(rejudge.level);
+        room.rejudges.lock().unwrap().push((job, sender));
+        // answered right away if there is nothing to evaluate
+        room.answer_rejudges(&game);
+        pending
+    };
+    for pending in pending {
+        room.queue_evaluation(pending);
+    }
+    Box::new(receiver.then(|result| {
+        let response = match result {
+            Ok(Some(result)) => {
+                log::info!("rejudged submissions, {} changed outcome", result.changed.len());
+                HttpResponse::Ok().json(result)
+            }
+            Ok(None) => {
+                log::warn!("rejudge was interrupted by a level change or another rejudge");
+                HttpResponse::Conflict().finish()
+            }
+            Err(_) => {
+                log::error!("evaluation workers are gone, rejudge can't finish");
+                HttpResponse::InternalServerError().finish()
+            }
+        };
+        Ok::<_, actix_web::Error>(response)
+    }))
+}
+
+fn rate_limit(room: CurrentRoom, limit: Json<contract::RateLimit>) -> HttpResponse {
     let limit = limit.into_inner();
-    if limit.admin_token != state.admin_token.as_ref() {
+    if limit.admin_token != room.admin_token {
         log::debug!("invalid admin token: {:?}", limit.admin_token);
         return HttpResponse::Unauthorized().finish();
     }
-    if state.users.iter().any(|u| u.name == limit.user) {
-        let mut game = state.game.lock().unwrap();
+    if room.users.iter().any(|u| u.name == limit.user) {
+        let mut game = room.game.lock().unwrap();
         game.rate_limit_user(&limit.user, RateLimit {
             count: limit.count as usize,
             window: Duration::seconds(i64::from(limit.window)),
         });
+        room.record(Event::RateLimit {
+            user: limit.user.clone(),
+            count: limit.count as usize,
+            window_seconds: i64::from(limit.window),
+        });
         HttpResponse::Ok().finish()
     } else {
         HttpResponse::NotFound().finish()
     }
 }
 
-fn authenticate(state: State<AppState>, auth: Json<contract::Authenticate>) -> HttpResponse {
+fn export_snapshot(room: CurrentRoom, export: Json<contract::ExportSnapshot>) -> HttpResponse {
+    let export = export.into_inner();
+    if export.admin_token != room.admin_token {
+        log::debug!("invalid admin token: {:?}", export.admin_token);
+        return HttpResponse::Unauthorized().finish();
+    }
+    let raw = room.game.lock().unwrap().raw_snapshot();
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(raw)
+}
+
+fn import_snapshot(room: CurrentRoom, import: Json<contract::ImportSnapshot>) -> HttpResponse {
+    let import = import.into_inner();
+    if import.admin_token != room.admin_token {
+        log::debug!("invalid admin token: {:?}", import.admin_token);
+        return HttpResponse::Unauthorized().finish();
+    }
+    let raw = import.snapshot.to_string();
+    let pending = {
+        let mut game = room.game.lock().unwrap();
+        match game.import_snapshot(&raw) {
+            Ok(pending) => {
+                room.record(Event::ImportSnapshot { snapshot: raw });
+                pending
+            }
+            Err(e) => {
+                log::warn!("failed to import snapshot: {}", e);
+                return HttpResponse::BadRequest().body(e);
+            }
+        }
+    };
+    for pending in pending {
+        room.queue_evaluation(pending);
+    }
+    HttpResponse::Ok().finish()
+}
+
+fn authenticate(room: CurrentRoom, auth: Json<contract::Authenticate>) -> HttpResponse {
     let auth = auth.into_inner();
-    if state.is_password_correct(&auth.user, &auth.password) {
+    if room.is_password_correct(&auth.user, &auth.password) {
         HttpResponse::Ok()
             .cookie(Cookie::build("user", auth.user)
                 .max_age(Dur::days(1))
@@ -164,12 +615,15 @@ fn index(_req: HttpRequest<AppState>) -> Result<NamedFile> {
     Ok(NamedFile::open("static/login.html")?)
 }
 
+/// The editor and login page only talk to the default room, users of other
+/// rooms use the API directly.
 fn editor(state: State<AppState>, request: HttpRequest<AppState>) -> Result<NamedFile> {
     let user_cookie = request.cookie("user");
     let password_cookie = request.cookie("password");
     let user = user_cookie.as_ref().map(|c| c.value()).unwrap_or("<missing>");
     let password = password_cookie.as_ref().map(|c| c.value()).unwrap_or("<missing>");
-    if state.is_password_correct(user, password) {
+    let default_room = &state.rooms[DEFAULT_ROOM];
+    if default_room.is_password_correct(user, password) {
         Ok(NamedFile::open("static/editor.html")?)
     } else {
         log::warn!(
@@ -195,7 +649,7 @@ struct Opt {
     /// Admin token (defaults to "admin")
     #[structopt(long = "admin")]
     admin_token: Option<String>,
-    /// Max steps for user program (defaults to 100)
+    /// Max steps for user program on levels without their own limit (defaults to 100)
     #[structopt(long = "max-steps")]
     max_steps: Option<u64>,
     /// Max submissions allowed in rate limit window (defaults to 2)
@@ -204,12 +658,60 @@ struct Opt {
     /// Length of rate limit window (in seconds, defaults to 10)
     #[structopt(long = "rate-limit-window")]
     rate_limit_window: Option<u32>,
+    /// Number of threads evaluating submissions (defaults to 2)
+    #[structopt(long = "workers")]
+    workers: Option<usize>,
+    /// Max submissions waiting for evaluation (defaults to 100)
+    #[structopt(long = "max-queued")]
+    max_queued: Option<usize>,
+    /// Step cap for sandbox evaluations (defaults to max steps)
+    #[structopt(long = "sandbox-max-steps")]
+    sandbox_max_steps: Option<u64>,
+    /// Max sandbox evaluations allowed in rate limit window (defaults to 5)
+    #[structopt(long = "sandbox-rate-limit-count")]
+    sandbox_rate_limit_count: Option<usize>,
+    /// Length of sandbox rate limit window (in seconds, defaults to 10)
+    #[structopt(long = "sandbox-rate-limit-window")]
+    sandbox_rate_limit_window: Option<u32>,
+    /// Time penalty (in seconds) for each failed submission before the first success on a level
+    #[structopt(long = "wrong-attempt-penalty")]
+    wrong_attempt_penalty: Option<u32>,
+    /// Freeze the public scoreboard this many minutes before a scheduled level closes
+    #[structopt(long = "freeze-minutes")]
+    freeze_minutes: Option<u32>,
+    /// Keep the scoreboard frozen after close until revealed through /api/admin/unfreeze
+    #[structopt(long = "manual-unfreeze")]
+    manual_unfreeze: bool,
+    /// Rate limit members of a team together instead of each user
+    #[structopt(long = "team-rate-limit")]
+    team_rate_limit: bool,
+    /// Who can see other users' submissions: "own", "after-close" (default) or "public"
+    #[structopt(long = "visibility", parse(try_from_str = "parse_visibility"))]
+    visibility: Option<Visibility>,
     /// Load global scores from previous scoreboard dump
     #[structopt(long = "scores", parse(from_os_str))]
     scores: Option<PathBuf>,
     /// Directory to dump scores after each level change
     #[structopt(long = "score-dir", parse(from_os_str))]
     score_dir: Option<PathBuf>,
+    /// Load full game state from a snapshot
+    #[structopt(long = "snapshot", parse(from_os_str))]
+    snapshot: Option<PathBuf>,
+    /// Write a full game snapshot into score dir every given number of seconds
+    #[structopt(long = "snapshot-interval")]
+    snapshot_interval: Option<u64>,
+    /// Number of periodic snapshots to keep (defaults to 10)
+    #[structopt(long = "snapshot-keep")]
+    snapshot_keep: Option<usize>,
+    /// Schedule of levels to open automatically
+    #[structopt(long = "schedule", parse(from_os_str))]
+    schedule: Option<PathBuf>,
+    /// Journal of all state changes, replayed on startup
+    #[structopt(long = "journal", parse(from_os_str))]
+    journal: Option<PathBuf>,
+    /// File listing additional contest rooms
+    #[structopt(long = "rooms", parse(from_os_str))]
+    rooms: Option<PathBuf>,
 }
 
 fn main() {
@@ -237,54 +739,126 @@ fn main() {
             User {
                 name: DEFAULT_USER_NAME.to_owned(),
                 password: DEFAULT_USER_PASSWORD.to_owned(),
+                team: None,
             },
         ]
     };
 
+    let max_steps = opt.max_steps.unwrap_or(100);
     let config = GameConfig {
-        max_steps: opt.max_steps.unwrap_or(100),
+        max_steps,
         rate_limit: RateLimit {
             count: opt.rate_limit_count.unwrap_or(2),
             window: Duration::seconds(i64::from(opt.rate_limit_window.unwrap_or(10))),
         },
+        max_queued: opt.max_queued.unwrap_or(100),
+        visibility: opt.visibility.unwrap_or(Visibility::AfterClose),
+        sandbox_max_steps: opt.sandbox_max_steps.unwrap_or(max_steps),
+        sandbox_rate_limit: RateLimit {
+            count: opt.sandbox_rate_limit_count.unwrap_or(5),
+            window: Duration::seconds(i64::from(opt.sandbox_rate_limit_window.unwrap_or(10))),
+        },
+        wrong_attempt_penalty: opt.wrong_attempt_penalty.map(|p| Duration::seconds(i64::from(p))),
+        scoreboard_freeze: opt.freeze_minutes.map(|m| Duration::minutes(i64::from(m))),
+        manual_unfreeze: opt.manual_unfreeze,
+        teams: HashMap::new(),
+        team_rate_limit: opt.team_rate_limit,
     };
 
-    let game = if let Some(scores) = opt.scores {
-        let json = match std::fs::read_to_string(&scores) {
-            Ok(json) => json,
+    let mut room_configs = vec![
+        RoomConfig {
+            name: DEFAULT_ROOM.to_owned(),
+            users,
+            admin_token: admin_token.to_owned(),
+            scores: opt.scores.clone(),
+            snapshot: opt.snapshot.clone(),
+            score_dir: opt.score_dir.clone(),
+            journal: opt.journal.clone(),
+            schedule: opt.schedule.clone(),
+        },
+    ];
+    if let Some(path) = &opt.rooms {
+        match config::read_rooms(path) {
+            Ok(rooms) => room_configs.extend(rooms),
             Err(e) => {
-                log::error!("failed to read scoreboard file: {}", e);
+                log::error!("failed to read rooms file: {}", e);
                 return;
             }
-        };
-        match PacmanGame::from_raw_scoreboard(config.clone(), &json) {
-            Ok(game) => game,
-            Err(()) => return,
         }
-    } else {
-        PacmanGame::new(config.clone())
+    }
+
+    let mut rooms = HashMap::new();
+    for room_config in room_configs {
+        let name = room_config.name.clone();
+        if rooms.contains_key(&name) {
+            log::error!("room {} is defined more than once", name);
+            return;
+        }
+        match open_room(room_config, &config, &opt) {
+            Ok(room) => {
+                rooms.insert(name, room);
+            }
+            Err(e) => {
+                log::error!("room {}: {}", name, e);
+                return;
+            }
+        }
+    }
+    let rooms = Arc::new(rooms);
+
+    let replay_images = match svg::Images::embed_from_dir(StdPath::new("static/images")) {
+        Ok(images) => images,
+        Err(e) => {
+            log::warn!("failed to load images for svg replays, linking them instead: {}", e);
+            svg::Images::from_base_url("/images")
+        }
     };
 
     let state = AppState {
-        game: Arc::new(Mutex::new(game)),
-        users: users.into(),
-        admin_token: admin_token.into(),
+        rooms: rooms.clone(),
         config,
-        score_dir: opt.score_dir.map(Into::into),
+        replay_images: Arc::new(replay_images),
     };
 
+    std::thread::Builder::new()
+        .name("scheduler".to_owned())
+        .spawn(move || run_scheduler(rooms))
+        .expect("failed to start scheduler thread");
+
+    // the same routes are served for the default room and under `/rooms/{room}`
+    macro_rules! api_routes {
+        ($routes:expr) => {
+            $routes
+                .resource("/submit", |r| r.post().with(submit))
+                .resource("/authenticate", |r| r.post().with(authenticate))
+                .resource("/sandbox", |r| r.post().with(evaluate_sandbox))
+                .resource("/submissions", |r| r.get().with(get_submissions))
+                .resource("/submissions/{id}", |r| r.get().with(get_submission))
+                .resource("/submissions/{id}/status", |r| r.get().with(get_submission_status))
+                .resource("/submissions/{id}/replay.svg", |r| r.get().with(get_submission_svg))
+                .resource("/me/submissions", |r| r.get().with(my_submissions))
+                .resource("/scoreboard", |r| r.get().with(scoreboard))
+                .resource("/schedule", |r| r.get().with(get_schedule))
+                .resource("/play/start", |r| r.post().with(start_play))
+                .resource("/play/move", |r| r.post().with(play_move))
+                .resource("/admin/level", |r| r.post().with(set_level))
+                .resource("/admin/levelstate", |r| r.post().with(set_level_state))
+                .resource("/admin/undo", |r| r.post().with(undo_level_change))
+                .resource("/admin/schedule", |r| r.post().with(set_schedule))
+                .resource("/admin/reset", |r| r.post().with(reset))
+                .resource("/admin/rejudge", |r| r.post().with(rejudge))
+                .resource("/admin/freeze", |r| r.post().with(freeze))
+                .resource("/admin/unfreeze", |r| r.post().with(unfreeze))
+                .resource("/admin/ratelimit", |r| r.post().with(rate_limit))
+                .resource("/admin/export", |r| r.post().with(export_snapshot))
+                .resource("/admin/import", |r| r.post().with(import_snapshot))
+        };
+    }
+
     let app_factory = move || vec![
-        App::with_state(state.clone())
+        api_routes!(App::with_state(state.clone())
             .prefix("/api")
-            .resource("/submit", |r| r.post().with(submit))
-            .resource("/authenticate", |r| r.post().with(authenticate))
-            .resource("/submissions", |r| r.get().with(get_submissions))
-            .resource("/submissions/{id}", |r| r.get().with(get_submission))
-            .resource("/scoreboard", |r| r.get().with(scoreboard))
-            .resource("/admin/level", |r| r.post().with(set_level))
-            .resource("/admin/levelstate", |r| r.post().with(set_level_state))
-            .resource("/admin/reset", |r| r.post().with(reset))
-            .resource("/admin/ratelimit", |r| r.post().with(rate_limit))
+            .scope("/rooms/{room}", |scope| api_routes!(scope)))
             .boxed(),
         App::new()
             .prefix("/images")
@@ -310,6 +884,126 @@ fn main() {
     let _ = actor_system.run();
 }
 
+/// Restores the room's game from its scores, snapshot and journal, and
+/// starts its evaluation workers.
+fn open_room(room: RoomConfig, config: &GameConfig, opt: &Opt) -> std::result::Result<Arc<Room>, String> {
+    let mut config = config.clone();
+    config.teams = room.users
+        .iter()
+        .filter_map(|u| u.team.clone().map(|team| (u.name.clone(), team)))
+        .collect();
+    let config = &config;
+
+    let mut game = if let Some(scores) = &room.scores {
+        let json = std::fs::read_to_string(scores)
+            .map_err(|e| format!("failed to read scoreboard file: {}", e))?;
+        PacmanGame::from_raw_scoreboard(config.clone(), &json)
+            .map_err(|()| "failed to load scoreboard".to_owned())?
+    } else {
+        PacmanGame::new(config.clone())
+    };
+
+    let snapshot = match &room.snapshot {
+        Some(path) => Some(std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read snapshot file: {}", e))?),
+        None => None,
+    };
+    let (mut journal, events) = match &room.journal {
+        Some(path) => {
+            let (journal, events) = Journal::open(path)
+                .map_err(|e| format!("failed to open journal: {}", e))?;
+            (Some(journal), events)
+        }
+        None => (None, Vec::new()),
+    };
+
+    if let Some(raw) = snapshot {
+        match (&mut journal, events.first()) {
+            // the journal starts from this snapshot, it is imported on replay
+            (Some(_), Some(Event::ImportSnapshot { snapshot })) if *snapshot == raw => {}
+            (Some(_), Some(_)) => {
+                return Err("the journal already has events, replaying them on top of the snapshot \
+                    would apply them twice (start without --snapshot, or with a new journal)".to_owned());
+            }
+            (journal, _) => {
+                // unevaluated submissions are queued along with the journal's ones
+                game.import_snapshot(&raw)
+                    .map_err(|e| format!("failed to load snapshot: {}", e))?;
+                if let Some(journal) = journal {
+                    journal.append(&Event::ImportSnapshot { snapshot: raw })
+                        .map_err(|e| format!("failed to write to journal: {}", e))?;
+                }
+            }
+        }
+    }
+
+    if journal.is_some() {
+        log::info!("room {}: replaying {} journal events", room.name, events.len());
+    }
+    for event in &events {
+        game.apply(event);
+    }
+    let journal = journal.map(Mutex::new);
+
+    let schedule = if let Some(path) = &room.schedule {
+        let schedule = config::read_schedule(path)
+            .map_err(|e| format!("failed to read schedule: {}", e))?;
+        let now = chrono::Utc::now();
+        let parsed = Schedule::from_contract(&schedule, now)
+            .map_err(|e| format!("invalid schedule: {}", e))?;
+        game.set_schedule(parsed, now);
+        Some(Event::SetSchedule { schedule, time: now })
+    } else {
+        None
+    };
+
+    // left unevaluated by the snapshot or the journal
+    let pending = game.pending_evaluations();
+
+    let game = Arc::new(Mutex::new(game));
+
+    match (opt.snapshot_interval, &room.score_dir) {
+        (Some(interval), Some(dir)) => snapshots::start_periodic(
+            game.clone(),
+            dir.clone(),
+            std::time::Duration::from_secs(interval),
+            opt.snapshot_keep.unwrap_or(10),
+        ),
+        (Some(_), None) => log::warn!("room {} has no score dir, not writing snapshots", room.name),
+        (None, _) => {}
+    }
+
+    let (evaluations, receiver) = mpsc::channel();
+    let room = Arc::new(Room {
+        name: room.name,
+        game,
+        evaluations: Mutex::new(evaluations),
+        rejudges: Mutex::new(Vec::new()),
+        users: room.users,
+        admin_token: room.admin_token,
+        score_dir: room.score_dir,
+        journal,
+        config: config.clone(),
+    });
+    if let Some(event) = schedule {
+        room.record(event);
+    }
+    worker::start_pool(room.clone(), receiver, opt.workers.unwrap_or(2));
+    for pending in pending {
+        room.queue_evaluation(pending);
+    }
+    Ok(room)
+}
+
+fn parse_visibility(text: &str) -> std::result::Result<Visibility, String> {
+    match text {
+        "own" => Ok(Visibility::Own),
+        "after-close" => Ok(Visibility::AfterClose),
+        "public" => Ok(Visibility::Public),
+        _ => Err(format!("unknown visibility: {}", text)),
+    }
+}
+
 fn setup_logger(verbose: bool) {
     let filter = if verbose {
         log::LevelFilter::Trace
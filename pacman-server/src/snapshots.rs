@@ -0,0 +1,62 @@
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use pacman_core::PacmanGame;
+
+const PREFIX: &str = "snapshot-";
+
+/// Periodically writes full game snapshots into `dir`, keeping only the
+/// `keep` newest ones.
+pub fn start_periodic(game: Arc<Mutex<PacmanGame>>, dir: PathBuf, interval: std::time::Duration, keep: usize) {
+    thread::Builder::new()
+        .name("snapshots".to_owned())
+        .spawn(move || loop {
+            thread::sleep(interval);
+            let raw = game.lock().unwrap().raw_snapshot();
+            match write(&dir, &raw) {
+                Ok(file) => log::info!("written game snapshot to {}", file.display()),
+                Err(e) => log::error!("failed to write game snapshot: {}", e),
+            }
+            if let Err(e) = prune(&dir, keep) {
+                log::error!("failed to remove old snapshots: {}", e);
+            }
+        })
+        .expect("failed to start snapshot thread");
+}
+
+/// Names files by UTC time with a fixed width, so that they sort in the
+/// order they were written. Existing files are never overwritten.
+fn write(dir: &Path, raw: &str) -> io::Result<PathBuf> {
+    let timestamp = chrono::Utc::now().format("%Y%m%dT%H%M%S%.6fZ");
+    let mut file = PathBuf::new();
+    file.push(dir);
+    file.push(format!("{}{}", PREFIX, timestamp));
+    file.set_extension("json");
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&file)?
+        .write_all(raw.as_bytes())?;
+    Ok(file)
+}
+
+fn prune(dir: &Path, keep: usize) -> io::Result<()> {
+    let mut snapshots = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with(PREFIX) {
+            snapshots.push((name, entry.path()));
+        }
+    }
+    // timestamps in names sort the same way as the times they represent
+    snapshots.sort();
+    let remove = snapshots.len().saturating_sub(keep);
+    for (_, path) in snapshots.into_iter().take(remove) {
+        std::fs::remove_file(&path)?;
+        log::debug!("removed old snapshot {}", path.display());
+    }
+    Ok(())
+}
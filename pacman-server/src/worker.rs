@@ -0,0 +1,40 @@
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::Receiver;
+use std::thread;
+use pacman_core::PendingEvaluation;
+use crate::Room;
+
+/// Starts `count` threads that evaluate submissions queued in the room
+/// without holding the game lock.
+pub fn start_pool(room: Arc<Room>, receiver: Receiver<PendingEvaluation>, count: usize) {
+    let receiver = Arc::new(Mutex::new(receiver));
+    for index in 0..count {
+        let room = room.clone();
+        let receiver = receiver.clone();
+        thread::Builder::new()
+            .name(format!("evaluator-{}-{}", room.name, index))
+            .spawn(move || run_worker(&room, &receiver))
+            .expect("failed to start evaluation worker");
+    }
+}
+
+fn run_worker(room: &Room, receiver: &Mutex<Receiver<PendingEvaluation>>) {
+    loop {
+        let pending = match receiver.lock().unwrap().recv() {
+            Ok(pending) => pending,
+            Err(_) => return,
+        };
+        {
+            let mut game = room.game.lock().unwrap();
+            if !game.start_evaluation(&pending) {
+                // e.g. left over from an interrupted rejudge
+                room.answer_rejudges(&game);
+                continue;
+            }
+        }
+        let id = pending.id;
+        let finished = pending.evaluate();
+        log::debug!("evaluated submission {}", id);
+        room.finish_evaluation(finished);
+    }
+}
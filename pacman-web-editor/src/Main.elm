@@ -4,6 +4,8 @@ import Browser
 import Browser.Navigation as Navigation
 import Http
 import Html exposing (Html)
+import Process
+import Task
 import Url.Builder
 import Json.Decode as Json exposing (Decoder)
 import Json.Encode as Encode exposing (Value)
@@ -38,9 +40,16 @@ type alias Rule =
   , nextState : State
   }
 
-type SubmitResponse = Success | RateLimitExceeded | LevelClosed | Unauthorised | Fail String
+type SubmitResponse = Success SubmissionId | RateLimitExceeded | LevelClosed | Unauthorised | TooManyRules | QueueFull | Fail String
 
-type SubmitStatus = Pending | NotStarted | Finished SubmitResponse
+type Outcome = Won | Lost | OutOfMoves
+
+type Evaluation = Queued | Running | Done (Maybe Outcome) | Dropped
+
+-- submission ids are only unique within a level
+type alias SubmissionId = { id : Int, level : Int }
+
+type SubmitStatus = Pending | NotStarted | Finished SubmitResponse | Evaluating SubmissionId Evaluation
 
 type alias Model =
   { rules : List Rule
@@ -56,6 +65,8 @@ type Msg
   | AddRule
   | Submit
   | Submitted SubmitResponse
+  | PollStatus SubmissionId
+  | StatusPolled SubmissionId (Result Http.Error Evaluation)
 
 main : Program () Model Msg
 main = Browser.document
@@ -70,15 +81,40 @@ init = { rules = [], submit = NotStarted }
 
 responseDecoder : Decoder SubmitResponse
 responseDecoder = 
-  Json.string
+  Json.field "response" Json.string
   |> Json.andThen (\str ->
     case str of
-      "ok" -> Json.succeed Success
+      "ok" ->
+        Json.map2 SubmissionId (Json.field "submissionId" Json.int) (Json.field "level" Json.int)
+        |> Json.map Success
       "rateLimitExceeded" -> Json.succeed RateLimitExceeded
       "levelClosed" -> Json.succeed LevelClosed
       "unauthorized" -> Json.succeed Unauthorised
+      "tooManyRules" -> Json.succeed TooManyRules
+      "queueFull" -> Json.succeed QueueFull
       _ -> Json.fail "bad response message")
 
+outcomeDecoder : Decoder Outcome
+outcomeDecoder =
+  Json.string
+  |> Json.andThen (\str ->
+    case str of
+      "success" -> Json.succeed Won
+      "fail" -> Json.succeed Lost
+      "outOfMoves" -> Json.succeed OutOfMoves
+      _ -> Json.fail "bad outcome")
+
+evaluationDecoder : Decoder Evaluation
+evaluationDecoder =
+  Json.field "status" Json.string
+  |> Json.andThen (\str ->
+    case str of
+      "queued" -> Json.succeed Queued
+      "running" -> Json.succeed Running
+      "done" -> Json.map Done (Json.field "outcome" (Json.nullable outcomeDecoder))
+      "dropped" -> Json.succeed Dropped
+      _ -> Json.fail "bad evaluation status")
+
 showHttpError : Http.Error -> String
 showHttpError err =
   case err of
@@ -168,6 +204,30 @@ submitProgram rules =
       , expect = Http.expectJson handleResult responseDecoder
       }
 
+pollInterval : Float
+pollInterval = 1000
+
+pollLater : SubmissionId -> Cmd Msg
+pollLater id =
+  Task.perform (always (PollStatus id)) (Process.sleep pollInterval)
+
+fetchStatus : SubmissionId -> Cmd Msg
+fetchStatus submission =
+  Http.get
+    { url =
+        Url.Builder.absolute
+          [ "api", "submissions", String.fromInt submission.id, "status" ]
+          [ Url.Builder.int "level" submission.level ]
+    , expect = Http.expectJson (StatusPolled submission) evaluationDecoder
+    }
+
+polledSubmission : SubmitStatus -> Maybe SubmissionId
+polledSubmission submit =
+  case submit of
+    Finished (Success id) -> Just id
+    Evaluating id _ -> Just id
+    _ -> Nothing
+
 setElement : Int -> Maybe a -> List a -> List a
 setElement index newElem list =
   case (index, newElem, list) of
@@ -229,10 +289,40 @@ update msg model =
       ( model
       , Navigation.load "/index.html"
       )
+    (Submitted (Success id), { rules, submit }) ->
+      ( { rules = rules, submit = Finished (Success id) }
+      , pollLater id
+      )
     (Submitted result, { rules, submit }) ->
       ( { rules = rules, submit = Finished result }
       , Cmd.none
       )
+    (PollStatus id, { rules, submit }) ->
+      if polledSubmission submit == Just id then
+        (model, fetchStatus id)
+      else
+        (model, Cmd.none)
+    (StatusPolled id result, { rules, submit }) ->
+      if polledSubmission submit /= Just id then
+        (model, Cmd.none)
+      else
+        case result of
+          Ok (Done outcome) ->
+            ( { rules = rules, submit = Evaluating id (Done outcome) }
+            , Cmd.none
+            )
+          Ok Dropped ->
+            ( { rules = rules, submit = Evaluating id Dropped }
+            , Cmd.none
+            )
+          Ok evaluation ->
+            ( { rules = rules, submit = Evaluating id evaluation }
+            , pollLater id
+            )
+          Err err ->
+            ( { rules = rules, submit = Finished (Fail (showHttpError err)) }
+            , Cmd.none
+            )
 
 newRule : Rule
 newRule =
@@ -499,10 +589,19 @@ viewSubmitStatus model =
     text = case model.submit of
       Pending -> "Submitting..."
       NotStarted -> ""
-      Finished Success -> "OK"
+      Finished (Success _) -> "Queued"
+      Evaluating _ Queued -> "Queued"
+      Evaluating _ Running -> "Evaluating..."
+      Evaluating _ (Done (Just Won)) -> "Solved!"
+      Evaluating _ (Done (Just Lost)) -> "Failed"
+      Evaluating _ (Done (Just OutOfMoves)) -> "Failed: out of moves"
+      Evaluating _ (Done Nothing) -> "Evaluated"
+      Evaluating _ Dropped -> "Not evaluated: level was changed"
       Finished RateLimitExceeded -> "Blocked: rate limited"
       Finished LevelClosed -> "Level closed"
       Finished Unauthorised -> "Unauthorized"
+      Finished TooManyRules -> "Too many rules"
+      Finished QueueFull -> "Blocked: evaluation queue is full"
       Finished (Fail msg) -> msg
   in
     Element.el TextStyle [ Attr.padding 10 ] (Element.text text)